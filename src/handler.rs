@@ -1,15 +1,25 @@
-use crate::{error::log_handler_err, shadowrun::shadowrun_reaction};
+use crate::{
+    error::log_handler_err,
+    shadowrun::{shadowrun_message, shadowrun_reaction, shadowrun_reaction_add},
+};
 use anyhow::Context as _;
 use serenity::{
     client::{Context, EventHandler},
-    model::channel::Reaction,
+    model::channel::{Message, Reaction},
 };
 
 pub struct Handler;
 impl EventHandler for Handler {
+    fn message(&self, ctx: Context, new_message: Message) {
+        handle!("message" for ctx, new_message => {
+            "shadowrun" => shadowrun_message,
+        });
+    }
+
     fn reaction_add(&self, ctx: Context, add_reaction: Reaction) {
         handle!("reaction_add" for ctx, add_reaction => {
             "shadowrun" => shadowrun_reaction,
+            "shadowrun_add" => shadowrun_reaction_add,
         });
     }
 
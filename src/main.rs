@@ -11,13 +11,20 @@ mod handler;
 mod help;
 mod shadowrun;
 mod state;
+mod store;
 mod string;
 mod utils;
 mod vote;
 
 use crate::{
-    admin::ADMIN_GROUP, edf::EDF_GROUP, error::log_cmd_err, general::GENERAL_GROUP,
-    handler::Handler, help::MY_HELP, shadowrun::SHADOWRUN_GROUP,
+    admin::ADMIN_GROUP,
+    edf::EDF_GROUP,
+    error::log_cmd_err,
+    general::GENERAL_GROUP,
+    handler::Handler,
+    help::MY_HELP,
+    shadowrun::{plan::PlanLockKey, SHADOWRUN_GROUP},
+    store::StoreLockKey,
 };
 use anyhow::Error;
 use dotenv::dotenv;
@@ -76,10 +83,12 @@ fn main() {
         interrupt_manager.lock().shutdown_all();
     })?;
 
-    client
-        .data
-        .write()
-        .insert::<ManagerKey>(client.shard_manager.clone());
+    {
+        let mut data = client.data.write();
+        data.insert::<ManagerKey>(client.shard_manager.clone());
+        data.insert::<StoreLockKey>(Arc::new(SerenityMutex::new(())));
+        data.insert::<PlanLockKey>(Arc::new(SerenityMutex::new(())));
+    }
 
     client.start()?;
 }
@@ -0,0 +1,40 @@
+use crate::shadowrun::roll::labelled_term;
+
+// the modifiers making up a value, as announced to the table
+#[derive(Clone, Default)]
+pub struct Breakdown {
+    modifiers: Vec<(String, i64)>,
+}
+
+impl Breakdown {
+    // every labelled term of an expression, such as `+2{smartlink}`
+    pub fn of_expr(mut input: &str) -> Self {
+        let mut breakdown = Breakdown::default();
+        while let Some(c) = input.chars().next() {
+            match labelled_term(input) {
+                Ok((rest, (value, label))) => {
+                    breakdown.push(label, value);
+                    input = rest;
+                }
+                Err(_) => input = &input[c.len_utf8()..],
+            }
+        }
+        breakdown
+    }
+
+    pub fn push(&mut self, label: &str, value: i64) {
+        self.modifiers.push((label.to_owned(), value));
+    }
+
+    pub fn render(&self) -> String {
+        if self.modifiers.is_empty() {
+            return String::new();
+        }
+        let modifiers: Vec<String> = self
+            .modifiers
+            .iter()
+            .map(|(label, value)| format!("{} {:+}", label, value))
+            .collect();
+        format!("Modificateurs : {}.\n", modifiers.join(", "))
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{
+    error::{ARes, AVoid},
+    shadowrun::{game_master, macros::is_name},
+    store,
+    utils::clap_name,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::group, Args},
+    model::channel::Message,
+    model::id::UserId,
+};
+use sparky_macros::cmd;
+use std::collections::{BTreeMap, HashMap};
+
+const STORE: &str = "characters";
+const ATTRIBUTES: &[&str] = &[
+    "bod", "agi", "rea", "str", "wil", "log", "int", "cha", "mag", "res",
+];
+const LIMITS: &[&str] = &["phys", "ment", "soc"];
+const EDGE: &str = "edge";
+
+type Characters = HashMap<u64, UserCharacters>;
+
+#[derive(Serialize, Deserialize, Default)]
+struct UserCharacters {
+    active: Option<String>,
+    sheets: BTreeMap<String, Character>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Character {
+    pub name: String,
+    pub attributes: BTreeMap<String, u64>,
+    pub skills: BTreeMap<String, u64>,
+    pub limits: BTreeMap<String, u64>,
+    pub edge: u64,
+    #[serde(default)]
+    pub physical_damage: u64,
+    #[serde(default)]
+    pub stun_damage: u64,
+    #[serde(default)]
+    pub edge_spent: u64,
+}
+
+#[group]
+#[prefix = "char"]
+#[description = "Fiches de personnage, dont les valeurs s’utilisent dans `sr roll` \
+(`!r agi+pistolets[phys]`)."]
+#[commands(new, set, show, select, list, delete)]
+pub struct Char;
+
+#[cmd]
+#[description = "Crée un personnage et le rend actif. Argument : nom du personnage."]
+fn new(ctx: &Context, msg: &Message, mut args: Args) {
+    let name = match args.single::<String>() {
+        Ok(name) => name,
+        Err(_) => {
+            msg.reply(ctx, usage("new", "NOM"))?;
+            return;
+        }
+    };
+    let created = store::update(ctx, STORE, |characters: &mut Characters| {
+        let user = characters.entry(msg.author.id.0).or_default();
+        if user.sheets.contains_key(&name) {
+            return false;
+        }
+        user.sheets.insert(
+            name.clone(),
+            Character {
+                name: name.clone(),
+                ..Character::default()
+            },
+        );
+        user.active = Some(name.clone());
+        true
+    })?;
+    if created {
+        msg.reply(ctx, format!("personnage **{}** créé et actif.", name))?;
+    } else {
+        msg.reply(ctx, format!("le personnage **{}** existe déjà.", name))?;
+    }
+}
+
+#[cmd]
+#[description = "Modifie le personnage actif. Arguments : paires `CLÉ VALEUR`.\n\
+Clés : attributs (bod, agi, rea, str, wil, log, int, cha, mag, res), limites (phys, ment, soc), \
+`edge` ou nom de compétence. Une compétence à 0 est retirée."]
+fn set(ctx: &Context, msg: &Message, mut args: Args) {
+    let mut changes = vec![];
+    while !args.is_empty() {
+        match (args.single::<String>(), args.single::<u64>()) {
+            (Ok(key), Ok(value)) if is_name(&key) => changes.push((key.to_lowercase(), value)),
+            _ => {
+                msg.reply(ctx, usage("set", "CLÉ VALEUR [CLÉ VALEUR…]"))?;
+                return;
+            }
+        }
+    }
+    if changes.is_empty() {
+        msg.reply(ctx, usage("set", "CLÉ VALEUR [CLÉ VALEUR…]"))?;
+        return;
+    }
+    let updated = update_active(ctx, msg.author.id, |sheet| {
+        for (key, value) in changes {
+            sheet.set(key, value);
+        }
+        sheet.clone()
+    })?;
+    match updated {
+        Some(sheet) => send_sheet(ctx, msg, &sheet)?,
+        None => no_active(ctx, msg)?,
+    }
+}
+
+#[cmd]
+#[description = "Affiche un personnage. Argument optionnel : nom, par défaut le personnage actif."]
+fn show(ctx: &Context, msg: &Message, mut args: Args) {
+    let name = args.single::<String>().ok();
+    let mut characters: Characters = store::load(STORE)?;
+    let mut user = characters.remove(&msg.author.id.0).unwrap_or_default();
+    match name.or(user.active) {
+        Some(name) => match user.sheets.remove(&name) {
+            Some(sheet) => send_sheet(ctx, msg, &sheet)?,
+            None => {
+                msg.reply(ctx, format!("personnage inconnu : **{}**.", name))?;
+            }
+        },
+        None => no_active(ctx, msg)?,
+    }
+}
+
+#[cmd]
+#[description = "Rend actif un autre personnage. Argument : nom du personnage."]
+fn select(ctx: &Context, msg: &Message, mut args: Args) {
+    let name = match args.single::<String>() {
+        Ok(name) => name,
+        Err(_) => {
+            msg.reply(ctx, usage("select", "NOM"))?;
+            return;
+        }
+    };
+    let selected = store::update(ctx, STORE, |characters: &mut Characters| {
+        let user = characters.entry(msg.author.id.0).or_default();
+        let exists = user.sheets.contains_key(&name);
+        if exists {
+            user.active = Some(name.clone());
+        }
+        exists
+    })?;
+    if selected {
+        msg.reply(ctx, format!("**{}** est désormais actif.", name))?;
+    } else {
+        msg.reply(ctx, format!("personnage inconnu : **{}**.", name))?;
+    }
+}
+
+#[cmd]
+#[description = "Liste ses personnages."]
+fn list(ctx: &Context, msg: &Message) {
+    let mut characters: Characters = store::load(STORE)?;
+    let user = characters.remove(&msg.author.id.0).unwrap_or_default();
+    if user.sheets.is_empty() {
+        msg.reply(ctx, "aucun personnage.")?;
+        return;
+    }
+    let list = user
+        .sheets
+        .keys()
+        .map(|name| {
+            if Some(name) == user.active.as_ref() {
+                format!("**{}** (actif)", name)
+            } else {
+                name.clone()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    msg.reply(ctx, format!("personnages : {}.", list))?;
+}
+
+#[cmd]
+#[description = "Supprime un personnage. Argument : nom du personnage."]
+fn delete(ctx: &Context, msg: &Message, mut args: Args) {
+    let name = match args.single::<String>() {
+        Ok(name) => name,
+        Err(_) => {
+            msg.reply(ctx, usage("delete", "NOM"))?;
+            return;
+        }
+    };
+    let deleted = store::update(ctx, STORE, |characters: &mut Characters| {
+        let user = characters.entry(msg.author.id.0).or_default();
+        if user.active.as_ref() == Some(&name) {
+            user.active = None;
+        }
+        user.sheets.remove(&name).is_some()
+    })?;
+    if deleted {
+        msg.reply(ctx, format!("personnage **{}** supprimé.", name))?;
+    } else {
+        msg.reply(ctx, format!("personnage inconnu : **{}**.", name))?;
+    }
+}
+
+pub fn active_character(user_id: UserId) -> ARes<Option<Character>> {
+    let mut characters: Characters = store::load(STORE)?;
+    Ok(characters.remove(&user_id.0).and_then(|mut user| {
+        let active = user.active?;
+        user.sheets.remove(&active)
+    }))
+}
+
+#[cmd]
+#[description = "Restaure la chance des personnages en début de séance, réservé au MJ. Arguments \
+optionnels : mentions des joueurs concernés, par défaut tous."]
+pub fn edge(ctx: &Context, msg: &Message) {
+    if msg.author.id != game_master()? {
+        msg.reply(ctx, "commande réservée au MJ.")?;
+        return;
+    }
+    let only: Vec<u64> = msg.mentions.iter().map(|user| user.id.0).collect();
+    let refreshed = store::update(ctx, STORE, |characters: &mut Characters| {
+        let mut refreshed = 0;
+        for (user_id, user) in characters.iter_mut() {
+            if only.is_empty() || only.contains(user_id) {
+                for sheet in user.sheets.values_mut() {
+                    sheet.edge_spent = 0;
+                    refreshed += 1;
+                }
+            }
+        }
+        refreshed
+    })?;
+    msg.reply(
+        ctx,
+        format!("chance restaurée pour {} personnages.", refreshed),
+    )?;
+}
+
+pub fn update_active<R>(
+    ctx: &Context,
+    user_id: UserId,
+    f: impl FnOnce(&mut Character) -> R,
+) -> ARes<Option<R>> {
+    store::update(ctx, STORE, |characters: &mut Characters| {
+        let UserCharacters { active, sheets } = characters.entry(user_id.0).or_default();
+        sheets.get_mut(active.as_ref()?).map(f)
+    })
+}
+
+impl Character {
+    pub fn value(&self, key: &str) -> Option<u64> {
+        let key = key.to_lowercase();
+        if key == EDGE {
+            Some(self.edge)
+        } else if ATTRIBUTES.contains(&key.as_str()) {
+            self.attributes.get(&key).cloned()
+        } else if LIMITS.contains(&key.as_str()) {
+            self.limits.get(&key).cloned()
+        } else {
+            self.skills.get(&key).cloned()
+        }
+    }
+
+    pub fn edge_left(&self) -> u64 {
+        self.edge.saturating_sub(self.edge_spent)
+    }
+
+    // the points left after spending, or those available if not enough
+    pub fn spend_edge(&mut self, cost: u64) -> Result<u64, u64> {
+        let left = self.edge_left();
+        if cost > left {
+            return Err(left);
+        }
+        self.edge_spent += cost;
+        Ok(left - cost)
+    }
+
+    fn set(&mut self, key: String, value: u64) {
+        if key == EDGE {
+            self.edge = value;
+        } else if ATTRIBUTES.contains(&key.as_str()) {
+            self.attributes.insert(key, value);
+        } else if LIMITS.contains(&key.as_str()) {
+            self.limits.insert(key, value);
+        } else if value == 0 {
+            self.skills.remove(&key);
+        } else {
+            self.skills.insert(key, value);
+        }
+    }
+}
+
+fn send_sheet(ctx: &Context, msg: &Message, sheet: &Character) -> AVoid {
+    let fmt_values = |values: &BTreeMap<String, u64>| {
+        if values.is_empty() {
+            "\u{200b}".to_owned()
+        } else {
+            values
+                .iter()
+                .map(|(key, value)| format!("{} **{}**", key, value))
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+    };
+    msg.channel_id.send_message(ctx, |m| {
+        m.embed(|e| {
+            e.title(format!("Shadowrun – {}", sheet.name)).fields(vec![
+                ("Attributs", fmt_values(&sheet.attributes), true),
+                ("Compétences", fmt_values(&sheet.skills), true),
+                ("Limites", fmt_values(&sheet.limits), true),
+                (
+                    "Chance",
+                    format!("{}/{}", sheet.edge_left(), sheet.edge),
+                    true,
+                ),
+            ])
+        })
+    })?;
+    Ok(())
+}
+
+pub fn no_active(ctx: &Context, msg: &Message) -> AVoid {
+    msg.reply(
+        ctx,
+        format!(
+            "aucun personnage actif, créez-en un avec `{} NOM`.",
+            clap_name("sr char new")
+        ),
+    )?;
+    Ok(())
+}
+
+fn usage(command: &str, arguments: &str) -> String {
+    format!(
+        "utilisation : `{} {}`.",
+        clap_name(format!("sr char {}", command).as_str()),
+        arguments
+    )
+}
@@ -0,0 +1,221 @@
+use crate::{
+    error::AVoid,
+    shadowrun::character::{active_character, no_active, update_active, Character},
+    utils::clap_name,
+};
+use nom::{
+    character::complete::{digit1, one_of},
+    combinator::{all_consuming, map_res},
+    sequence::pair,
+    IResult,
+};
+use serenity::{
+    client::Context, framework::standard::Args, model::channel::Message, utils::Colour,
+};
+use sparky_macros::cmd;
+use std::{cmp::min, str::FromStr};
+
+const BOXES_PER_MODIFIER: u64 = 3;
+
+#[cmd]
+#[description = "Inflige des dommages au personnage actif. Argument : `NP` (physiques) ou `NS` \
+(étourdissants), sans argument affiche ses moniteurs de condition."]
+pub fn dmg(ctx: &Context, msg: &Message, args: Args) {
+    let damage = match args.rest() {
+        "" => None,
+        rest => match all_consuming(damage_expr)(rest) {
+            Ok((_, damage)) => Some(damage),
+            Err(_) => {
+                msg.reply(ctx, usage("dmg"))?;
+                return;
+            }
+        },
+    };
+    let sheet = match damage {
+        Some((boxes, physical)) => update_active(ctx, msg.author.id, |sheet| {
+            sheet.take_damage(boxes, physical);
+            sheet.clone()
+        })?,
+        None => active_character(msg.author.id)?,
+    };
+    match sheet {
+        Some(sheet) => send_monitors(ctx, msg, &sheet)?,
+        None => no_active(ctx, msg)?,
+    }
+}
+
+#[cmd]
+#[description = "Soigne le personnage actif. Argument : `NP` (physiques) ou `NS` (étourdissants), \
+sans argument soigne tout."]
+pub fn heal(ctx: &Context, msg: &Message, args: Args) {
+    let healing = match args.rest() {
+        "" => None,
+        rest => match all_consuming(damage_expr)(rest) {
+            Ok((_, healing)) => Some(healing),
+            Err(_) => {
+                msg.reply(ctx, usage("heal"))?;
+                return;
+            }
+        },
+    };
+    let sheet = update_active(ctx, msg.author.id, |sheet| {
+        match healing {
+            Some((boxes, true)) => {
+                sheet.physical_damage = sheet.physical_damage.saturating_sub(boxes)
+            }
+            Some((boxes, false)) => sheet.stun_damage = sheet.stun_damage.saturating_sub(boxes),
+            None => {
+                sheet.physical_damage = 0;
+                sheet.stun_damage = 0;
+            }
+        }
+        sheet.clone()
+    })?;
+    match sheet {
+        Some(sheet) => send_monitors(ctx, msg, &sheet)?,
+        None => no_active(ctx, msg)?,
+    }
+}
+
+impl Character {
+    pub fn physical_monitor(&self) -> u64 {
+        half_up(self.value("bod").unwrap_or(0)).saturating_add(8)
+    }
+
+    pub fn stun_monitor(&self) -> u64 {
+        half_up(self.value("wil").unwrap_or(0)).saturating_add(8)
+    }
+
+    // physical boxes beyond the monitor count as overflow, up to the body attribute; no more
+    // boxes than both monitors and the overflow can hold are taken into account
+    fn take_damage(&mut self, boxes: u64, physical: bool) {
+        let max_physical = self
+            .physical_monitor()
+            .saturating_add(self.value("bod").unwrap_or(0));
+        let boxes = min(boxes, max_physical.saturating_add(self.stun_monitor()));
+        let mut physical_boxes = if physical { boxes } else { 0 };
+        if !physical {
+            self.stun_damage = self.stun_damage.saturating_add(boxes);
+            let stun_monitor = self.stun_monitor();
+            if self.stun_damage > stun_monitor {
+                physical_boxes = self.stun_damage - stun_monitor;
+                self.stun_damage = stun_monitor;
+            }
+        }
+        self.physical_damage = min(
+            self.physical_damage.saturating_add(physical_boxes),
+            max_physical,
+        );
+    }
+
+    pub fn wound_modifier(&self) -> u64 {
+        min(self.physical_damage, self.physical_monitor()) / BOXES_PER_MODIFIER
+            + self.stun_damage / BOXES_PER_MODIFIER
+    }
+}
+
+fn send_monitors(ctx: &Context, msg: &Message, sheet: &Character) -> AVoid {
+    let physical_monitor = sheet.physical_monitor();
+    let stun_monitor = sheet.stun_monitor();
+    let mut status = format!(
+        "Modificateur de blessures : **-{}**.",
+        sheet.wound_modifier()
+    );
+    if sheet.physical_damage > physical_monitor {
+        status.push_str(&format!(
+            "\nMourant, débordement de {} cases.",
+            sheet.physical_damage - physical_monitor
+        ));
+    } else if sheet.physical_damage == physical_monitor {
+        status.push_str("\nHors de combat.");
+    } else if sheet.stun_damage == stun_monitor {
+        status.push_str("\nInconscient.");
+    }
+    msg.channel_id.send_message(ctx, |m| {
+        m.embed(|e| {
+            e.title(format!("Shadowrun – État de {}", sheet.name))
+                .colour(Colour::DARK_RED)
+                .description(status)
+                .field(
+                    format!(
+                        "Physique ({}/{})",
+                        min(sheet.physical_damage, physical_monitor),
+                        physical_monitor
+                    ),
+                    boxes(sheet.physical_damage, physical_monitor),
+                    false,
+                )
+                .field(
+                    format!("Étourdissant ({}/{})", sheet.stun_damage, stun_monitor),
+                    boxes(sheet.stun_damage, stun_monitor),
+                    false,
+                )
+        })
+    })?;
+    Ok(())
+}
+
+fn boxes(filled: u64, monitor: u64) -> String {
+    (0..monitor)
+        .map(|i| {
+            let single = if i < filled { "■" } else { "□" };
+            if i > 0 && i % BOXES_PER_MODIFIER == 0 {
+                format!(" {}", single)
+            } else {
+                single.to_owned()
+            }
+        })
+        .collect()
+}
+
+fn half_up(value: u64) -> u64 {
+    value / 2 + value % 2
+}
+
+fn usage(command: &str) -> String {
+    format!(
+        "utilisation : `{} NP` ou `NS`.",
+        clap_name(format!("sr {}", command).as_str())
+    )
+}
+
+pub fn damage_expr(input: &str) -> IResult<&str, (u64, bool)> {
+    let (input, (boxes, kind)) = pair(map_res(digit1, u64::from_str), one_of("PpSs"))(input)?;
+    Ok((input, (boxes, kind == 'P' || kind == 'p')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner() -> Character {
+        let mut sheet = Character::default();
+        sheet.attributes.insert("bod".to_owned(), 4);
+        sheet.attributes.insert("wil".to_owned(), 3);
+        sheet
+    }
+
+    #[test]
+    fn stun_overflows_into_physical() {
+        let mut sheet = runner();
+        sheet.take_damage(12, false);
+        assert_eq!((sheet.stun_damage, sheet.physical_damage), (10, 2));
+        sheet.take_damage(3, true);
+        assert_eq!(sheet.physical_damage, 5);
+        assert_eq!(sheet.wound_modifier(), 1 + 3);
+    }
+
+    #[test]
+    fn damage_is_capped() {
+        let mut sheet = runner();
+        sheet.take_damage(u64::MAX, false);
+        assert_eq!((sheet.stun_damage, sheet.physical_damage), (10, 14));
+        sheet.take_damage(u64::MAX, true);
+        sheet.take_damage(u64::MAX, false);
+        assert_eq!((sheet.stun_damage, sheet.physical_damage), (10, 14));
+        let mut sheet = runner();
+        sheet.attributes.insert("bod".to_owned(), u64::MAX);
+        sheet.take_damage(u64::MAX, true);
+        assert_eq!(sheet.physical_damage, u64::MAX);
+    }
+}
@@ -6,7 +6,10 @@ use crate::{
     discord::{pop_self, reaction_is_own},
     error::{ARes, AVoid},
     help::{clap_help, clap_settings},
-    shadowrun::RUNNER,
+    shadowrun::{
+        plan::{ShadowrunPlan, Slot, SLOT_NAMES},
+        RUNNER,
+    },
     state::{encode, Embedded},
     state::{extract, find_by_state},
     utils::{clap_name, MapExt},
@@ -14,6 +17,7 @@ use crate::{
 use anyhow::{anyhow, bail, Context as _, Error};
 use boolinator::Boolinator;
 use chrono::{Date, Datelike, Duration, NaiveTime, TimeZone, Timelike, Weekday};
+use chrono_tz::Tz;
 use clap::{App, Arg};
 use fehler::throws;
 use serde::{Deserialize, Serialize};
@@ -23,7 +27,7 @@ use serenity::{
     model::channel::ReactionType::Unicode,
     model::channel::{Message, Reaction},
     model::guild::Role,
-    model::id::UserId,
+    model::id::{ChannelId, UserId},
     model::user::User,
     utils::MessageBuilder,
 };
@@ -73,6 +77,20 @@ pub fn confirm(ctx: &Context, msg: &Message, args: Args) {
                     "l", "a", "e", "j", "v", "s", "d", "L", "A", "E", "J", "V", "S", "D",
                 ]),
         )
+        .arg(
+            Arg::with_name("week")
+                .short("w")
+                .takes_value(true)
+                .help("Semaine du planning, pour un planning de plusieurs semaines.")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("slot")
+                .short("s")
+                .takes_value(true)
+                .possible_values(SLOT_NAMES)
+                .help("Créneau du planning, pour un planning par créneaux."),
+        )
         .arg(
             Arg::with_name("online")
                 .short("o")
@@ -82,8 +100,7 @@ pub fn confirm(ctx: &Context, msg: &Message, args: Args) {
             Arg::with_name("time")
                 .short("t")
                 .takes_value(true)
-                .help("Horaire proposé par défaut.")
-                .default_value("20"),
+                .help("Horaire proposé par défaut. Par défaut celui du créneau, sinon 20h."),
         )
         .arg(
             Arg::with_name("alt-time")
@@ -100,22 +117,39 @@ pub fn confirm(ctx: &Context, msg: &Message, args: Args) {
         Some(args) => args,
         None => return,
     };
-    let plan = last_plan(ctx, msg)?;
+    let week = args
+        .value_of("week")
+        .context("unreachable: default value")?
+        .parse::<u8>()
+        .ok()
+        .and_then(|week| week.checked_sub(1));
+    let slot = args.value_of("slot").and_then(Slot::from_name);
+    let (plan, data) = match week {
+        Some(week) => last_plan(ctx, msg, week, slot)?,
+        None => {
+            msg.reply(ctx, "Erreur : semaine invalide.")?;
+            return;
+        }
+    };
     let day = fr_weekday_from_shorthand(
         args.value_of("JOUR")
             .ok_or_else(|| anyhow!("unreachable: unspecified day"))?,
     )?;
+    let date = match data.days().into_iter().find(|date| date.weekday() == day) {
+        Some(date) => date,
+        None => {
+            msg.reply(ctx, "Erreur : ce jour n’est pas dans le planning.")?;
+            return;
+        }
+    };
     let online = args.is_present("online");
-    let (participants, date) = read_participants_date(ctx, &plan, day, online, TZ_DEFAULT)?;
-    let time = parse_time_emote_like(
-        args.value_of("time")
-            .context("unreachable: default value")?,
-    )?;
-    let mut reactions = vec!["✅", "🚫"];
-    if !online {
-        reactions.append(&mut vec!["🏠", "🚩"]);
-    }
+    let participants = read_participants(ctx, &plan, day, online)?;
+    let time = match args.value_of("time") {
+        Some(time) => parse_time_emote_like(time)?,
+        None => default_time(slot),
+    };
     let mut alt_times = vec![];
+    let mut alt_emotes = vec![];
     if let Some(it) = args.values_of("alt-time") {
         for alt_time_str in it {
             let alt_time = parse_time_emote_like(alt_time_str)?;
@@ -128,30 +162,67 @@ pub fn confirm(ctx: &Context, msg: &Message, args: Args) {
                 )?;
                 return;
             }
-            if reactions.contains(&emote) {
+            if alt_emotes.contains(&emote) {
                 msg.reply(ctx, "Erreur : une emote horaire est dupliquée.")?;
                 return;
             }
-            alt_times.push(time_to_serial(alt_time)?);
-            reactions.push(emote);
+            alt_times.push(alt_time);
+            alt_emotes.push(emote);
         }
-    } else {
+    }
+    post(
+        ctx,
+        msg.channel_id,
+        date,
+        &participants,
+        online,
+        time,
+        alt_times,
+    )?;
+}
+
+pub fn default_time(slot: Option<Slot>) -> NaiveTime {
+    slot.map_or(NaiveTime::from_hms(20, 0, 0), Slot::default_time)
+}
+
+// without alternative times, the three following half-hours are proposed
+pub fn post(
+    ctx: &Context,
+    channel_id: ChannelId,
+    date: Date<Tz>,
+    participants: &[User],
+    online: bool,
+    time: NaiveTime,
+    alt_times: Vec<NaiveTime>,
+) -> AVoid {
+    let mut reactions = vec!["✅", "🚫"];
+    if !online {
+        reactions.append(&mut vec!["🏠", "🚩"]);
+    }
+    if alt_times.is_empty() {
         for i in 1..=3 {
             reactions.push(time_emote(time + Duration::minutes(30 * i))?);
         }
+    } else {
+        for &alt_time in &alt_times {
+            reactions.push(time_emote(alt_time)?);
+        }
     }
     let data = ShadowrunConfirm {
         date_timestamp: date.and_hms(12, 0, 0).timestamp(),
         participants_raw_ids: participants.iter().map(|u| u.id.0).collect(),
         online,
         time: time_to_serial(time)?,
-        alt_times,
+        alt_times: alt_times
+            .into_iter()
+            .map(time_to_serial)
+            .collect::<ARes<_>>()?,
     };
     std::thread::sleep(std::time::Duration::from_secs(2));
-    let mut msg = msg.channel_id.send_message(ctx, |m| {
+    let mut msg = channel_id.send_message(ctx, |m| {
         m.content({
             let mut mb = MessageBuilder::new();
-            for participant in &participants {
+            for participant in participants {
                 mb.mention(participant);
                 mb.push(" ");
             }
@@ -161,6 +232,7 @@ pub fn confirm(ctx: &Context, msg: &Message, args: Args) {
         .reactions(reactions)
     })?;
     refresh(ctx, &mut msg, data).context("refresh embed")?;
+    Ok(())
 }
 
 pub fn react(ctx: &Context, reaction: &Reaction) -> AVoid {
@@ -410,22 +482,49 @@ enum Hosting {
     Demanded,
 }
 
-fn last_plan(ctx: &Context, base: &Message) -> ARes<Message> {
-    if let Ok((msg, _)) = find_by_state(ctx, base, |d| matches!(d, Embedded::EShadowrunPlan(_))) {
-        Ok(msg)
+// the given week and slot of the latest plan
+fn last_plan(
+    ctx: &Context,
+    base: &Message,
+    week: u8,
+    slot: Option<Slot>,
+) -> ARes<(Message, ShadowrunPlan)> {
+    let latest = match find_by_state(ctx, base, |d| matches!(d, Embedded::EShadowrunPlan(_))) {
+        Ok((msg, Embedded::EShadowrunPlan(data))) => Some((msg, data)),
+        _ => None,
+    };
+    let found = match latest {
+        Some((msg, data)) if data.week == week && data.slot == slot => Some((msg, data)),
+        Some((_, latest)) if week < latest.weeks() => {
+            match find_by_state(ctx, base, |d| {
+                matches!(d, Embedded::EShadowrunPlan(data)
+                    if data.start_timestamp == latest.start_timestamp
+                        && data.week == week
+                        && data.slot == slot)
+            }) {
+                Ok((msg, Embedded::EShadowrunPlan(data))) => Some((msg, data)),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    if let Some(found) = found {
+        Ok(found)
     } else {
-        base.reply(ctx, "je n’ai pas trouvé le dernier planning.")?;
+        base.reply(
+            ctx,
+            "je n’ai pas trouvé cette semaine ou ce créneau du dernier planning.",
+        )?;
         bail!("could not find plan message")
     }
 }
 
-fn read_participants_date<T: TimeZone>(
+pub fn read_participants(
     ctx: &Context,
     plan: &Message,
     day: Weekday,
     online: bool,
-    tz: T,
-) -> ARes<(Vec<User>, Date<T>)> {
+) -> ARes<Vec<User>> {
     let mut participants = plan.reaction_users(
         ctx,
         Unicode(fr_weekday_to_emote(day).to_owned()),
@@ -441,9 +540,5 @@ fn read_participants_date<T: TimeZone>(
             .collect();
         participants.retain(|u| !only_online.contains(&u.id))
     }
-    let mut date = plan.timestamp.with_timezone(&tz).date();
-    while date.weekday() != day {
-        date = date.succ();
-    }
-    Ok((participants, date))
+    Ok(participants)
 }
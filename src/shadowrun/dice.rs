@@ -0,0 +1,515 @@
+use crate::shadowrun::roll::Resolution;
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, digit1, one_of},
+    combinator::{all_consuming, map, map_res, opt, verify},
+    multi::many0,
+    sequence::{pair, preceded, tuple},
+    IResult,
+};
+use rand::{
+    distributions::{Distribution, Uniform},
+    Rng,
+};
+use serde::{Deserialize, Serialize};
+use serenity::utils::MessageBuilder;
+use std::str::FromStr;
+
+const MAX_DICE: u64 = 100;
+const MAX_FACES: u64 = 1000;
+// keeps totals and negated constants far from overflowing
+const MAX_CONSTANT: u64 = 1_000_000;
+const MAX_EXPLOSIONS: usize = 20;
+const MAX_EXTRA_TENS: u64 = 2;
+const FATE_DICE: u64 = 4;
+const FATE_LADDER: &[(i64, &str)] = &[
+    (8, "Légendaire"),
+    (7, "Épique"),
+    (6, "Fantastique"),
+    (5, "Superbe"),
+    (4, "Excellent"),
+    (3, "Bon"),
+    (2, "Correct"),
+    (1, "Moyen"),
+    (0, "Médiocre"),
+    (-1, "Faible"),
+    (-2, "Terrible"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum DiceSystem {
+    Generic,
+    Fate,
+    Percentile,
+}
+
+#[derive(Clone)]
+pub enum DiceTest {
+    Generic(GenericRoll),
+    Fate(FateRoll),
+    Percentile(PercentileRoll),
+}
+
+#[derive(Clone)]
+pub struct GenericRoll {
+    pub terms: Vec<(bool, GenericTerm)>,
+}
+
+#[derive(Clone)]
+pub enum GenericTerm {
+    Dice {
+        count: u64,
+        faces: u64,
+        explode: bool,
+        keep: Option<Keep>,
+    },
+    Constant(u64),
+}
+
+#[derive(Clone, Copy)]
+pub enum Keep {
+    Highest(u64),
+    Lowest(u64),
+}
+
+#[derive(Clone)]
+pub struct FateRoll {
+    pub dice: u64,
+    pub modifier: i64,
+}
+
+// positive extra tens are bonus dice, negative ones penalty dice
+#[derive(Clone)]
+pub struct PercentileRoll {
+    pub target: Option<u64>,
+    pub extra_tens: i64,
+}
+
+impl DiceSystem {
+    pub fn parse(self, input: &str) -> Option<DiceTest> {
+        let parsed = match self {
+            DiceSystem::Generic => all_consuming(map(generic_roll, DiceTest::Generic))(input),
+            DiceSystem::Fate => all_consuming(map(fate_roll, DiceTest::Fate))(input),
+            DiceSystem::Percentile => {
+                all_consuming(map(percentile_roll, DiceTest::Percentile))(input)
+            }
+        };
+        parsed.ok().map(|(_, test)| test)
+    }
+}
+
+pub fn resolve(rng: &mut impl Rng, test: DiceTest) -> Resolution {
+    let (summary, details) = match test {
+        DiceTest::Generic(GenericRoll { terms }) => {
+            let mut total: i64 = 0;
+            let mut details = vec![];
+            for (negative, term) in terms {
+                let value = match term {
+                    GenericTerm::Dice {
+                        count,
+                        faces,
+                        explode,
+                        keep,
+                    } => {
+                        let (value, detail) = roll_dice(rng, count, faces, explode, keep);
+                        details.push(detail);
+                        value
+                    }
+                    GenericTerm::Constant(value) => value,
+                } as i64;
+                total += if negative { -value } else { value };
+            }
+            (format!("Total : {}.", total), details.join("\n"))
+        }
+        DiceTest::Fate(FateRoll { dice, modifier }) => {
+            let faces: Vec<i64> = (0..dice)
+                .map(|_| Uniform::new_inclusive(-1, 1).sample(rng))
+                .collect();
+            let total = faces.iter().sum::<i64>() + modifier;
+            let symbols: Vec<&str> = faces
+                .iter()
+                .map(|face| match face {
+                    1 => "+",
+                    -1 => "−",
+                    _ => "▫",
+                })
+                .collect();
+            (
+                format!("Résultat : {:+}, {}.", total, fate_rank(total)),
+                format!(
+                    "Dés : {} | modificateur : {:+}.",
+                    symbols.join(" "),
+                    modifier
+                ),
+            )
+        }
+        DiceTest::Percentile(PercentileRoll { target, extra_tens }) => {
+            let units = Uniform::new_inclusive(0, 9).sample(rng);
+            let tens: Vec<u64> = (0..=extra_tens.abs())
+                .map(|_| Uniform::new_inclusive(0, 9).sample(rng) * 10)
+                .collect();
+            let values = tens.iter().map(|ten| match ten + units {
+                0 => 100,
+                value => value,
+            });
+            let result = if extra_tens < 0 {
+                values.max()
+            } else {
+                values.min()
+            }
+            .unwrap_or(100);
+            let summary = match target {
+                Some(target) => format!(
+                    "{} ({}/{}).",
+                    percentile_rank(result, target),
+                    result,
+                    target
+                ),
+                None => format!("Résultat : {}.", result),
+            };
+            let tens: Vec<String> = tens.iter().map(|ten| format!("{:02}", ten)).collect();
+            (
+                summary,
+                format!("Dizaines : {} | unités : {}.", tens.join(", "), units),
+            )
+        }
+    };
+    Resolution {
+        summary,
+        hits: None,
+        details,
+        second_chance: None,
+        rolls: vec![],
+    }
+}
+
+// each kept die sums its explosions, dropped dice are struck through
+fn roll_dice(
+    rng: &mut impl Rng,
+    count: u64,
+    faces: u64,
+    explode: bool,
+    keep: Option<Keep>,
+) -> (u64, String) {
+    let die = Uniform::new_inclusive(1, faces);
+    let chains: Vec<Vec<u64>> = (0..count)
+        .map(|_| {
+            let mut chain = vec![die.sample(rng)];
+            while explode && chain.last() == Some(&faces) && chain.len() <= MAX_EXPLOSIONS {
+                chain.push(die.sample(rng));
+            }
+            chain
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..chains.len()).collect();
+    order.sort_by_key(|&i| chains[i].iter().sum::<u64>());
+    let kept: Vec<usize> = match keep {
+        Some(Keep::Highest(n)) => order.into_iter().rev().take(n as usize).collect(),
+        Some(Keep::Lowest(n)) => order.into_iter().take(n as usize).collect(),
+        None => order,
+    };
+    let mut mb = MessageBuilder::new();
+    mb.push(format!(
+        "{}d{}{}{} : ",
+        count,
+        faces,
+        if explode { "!" } else { "" },
+        match keep {
+            Some(Keep::Highest(n)) => format!("kh{}", n),
+            Some(Keep::Lowest(n)) => format!("kl{}", n),
+            None => String::new(),
+        }
+    ));
+    for (i, chain) in chains.iter().enumerate() {
+        if i > 0 {
+            mb.push(", ");
+        }
+        let shown = chain
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join("+");
+        if kept.contains(&i) {
+            mb.push(shown);
+        } else {
+            mb.push_strike(shown);
+        }
+    }
+    let total = kept.iter().map(|&i| chains[i].iter().sum::<u64>()).sum();
+    mb.push(format!(" = **{}**", total));
+    (total, mb.0)
+}
+
+fn fate_rank(total: i64) -> String {
+    FATE_LADDER
+        .iter()
+        .find(|(value, _)| *value <= total)
+        .map(|(value, name)| {
+            if *value == total {
+                (*name).to_owned()
+            } else {
+                format!("{} et plus", name)
+            }
+        })
+        .unwrap_or_else(|| "pire que Terrible".to_owned())
+}
+
+fn percentile_rank(result: u64, target: u64) -> &'static str {
+    if result == 1 {
+        "Réussite critique"
+    } else if result == 100 || (result >= 96 && target < 50) {
+        "Maladresse"
+    } else if result <= target / 5 {
+        "Réussite extrême"
+    } else if result <= target / 2 {
+        "Réussite majeure"
+    } else if result <= target {
+        "Réussite"
+    } else {
+        "Échec"
+    }
+}
+
+fn generic_roll(input: &str) -> IResult<&str, GenericRoll> {
+    let (input, head) = generic_term(input)?;
+    let (input, tail) = many0(pair(one_of("+-"), generic_term))(input)?;
+    let mut terms = vec![(false, head)];
+    terms.extend(tail.into_iter().map(|(sign, term)| (sign == '-', term)));
+    Ok((input, GenericRoll { terms }))
+}
+
+fn generic_term(input: &str) -> IResult<&str, GenericTerm> {
+    alt((dice_term, map(constant, GenericTerm::Constant)))(input)
+}
+
+fn dice_term(input: &str) -> IResult<&str, GenericTerm> {
+    let (input, (count, _, faces, explode, keep)) = verify(
+        tuple((
+            opt(number),
+            one_of("dD"),
+            number,
+            opt(char('!')),
+            opt(alt((
+                map(preceded(tag("kh"), number), Keep::Highest),
+                map(preceded(tag("kl"), number), Keep::Lowest),
+            ))),
+        )),
+        |(count, _, faces, explode, keep)| {
+            let count = count.unwrap_or(1);
+            (1..=MAX_DICE).contains(&count)
+                && (1..=MAX_FACES).contains(faces)
+                && (explode.is_none() || *faces > 1)
+                && match keep {
+                    Some(Keep::Highest(n)) | Some(Keep::Lowest(n)) => *n <= count,
+                    None => true,
+                }
+        },
+    )(input)?;
+    Ok((
+        input,
+        GenericTerm::Dice {
+            count: count.unwrap_or(1),
+            faces,
+            explode: explode.is_some(),
+            keep,
+        },
+    ))
+}
+
+fn fate_roll(input: &str) -> IResult<&str, FateRoll> {
+    alt((
+        map(pair(fate_dice, opt(signed)), |(dice, modifier)| FateRoll {
+            dice,
+            modifier: modifier.unwrap_or(0),
+        }),
+        map(signed, |modifier| FateRoll {
+            dice: FATE_DICE,
+            modifier,
+        }),
+    ))(input)
+}
+
+fn fate_dice(input: &str) -> IResult<&str, u64> {
+    let (input, (dice, _)) = pair(
+        opt(verify(number, |dice| (1..=MAX_DICE).contains(dice))),
+        alt((tag("dF"), tag("df"))),
+    )(input)?;
+    Ok((input, dice.unwrap_or(FATE_DICE)))
+}
+
+fn signed(input: &str) -> IResult<&str, i64> {
+    let (input, (sign, value)) = pair(opt(one_of("+-")), constant)(input)?;
+    Ok((
+        input,
+        if sign == Some('-') {
+            -(value as i64)
+        } else {
+            value as i64
+        },
+    ))
+}
+
+fn percentile_roll(input: &str) -> IResult<&str, PercentileRoll> {
+    let (input, target) = alt((map(tag("d100"), |_| None), map(number, Some)))(input)?;
+    let (input, extra) = opt(pair(
+        one_of("bp"),
+        verify(number, |extra| (1..=MAX_EXTRA_TENS).contains(extra)),
+    ))(input)?;
+    let extra_tens = match extra {
+        Some(('p', extra)) => -(extra as i64),
+        Some((_, extra)) => extra as i64,
+        None => 0,
+    };
+    Ok((input, PercentileRoll { target, extra_tens }))
+}
+
+fn number(input: &str) -> IResult<&str, u64> {
+    map_res(digit1, u64::from_str)(input)
+}
+
+fn constant(input: &str) -> IResult<&str, u64> {
+    verify(number, |value| *value <= MAX_CONSTANT)(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    // draws the given values in order, each as an offset from the lowest one of a range of the
+    // given size
+    struct Draws<'a>(std::slice::Iter<'a, (u64, u64)>);
+
+    impl RngCore for Draws<'_> {
+        fn next_u32(&mut self) -> u32 {
+            unimplemented!()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let (offset, range) = *self.0.next().expect("no draw left");
+            ((u128::from(offset) << 64) / u128::from(range) + 1) as u64
+        }
+
+        fn fill_bytes(&mut self, _: &mut [u8]) {
+            unimplemented!()
+        }
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), rand::Error> {
+            unimplemented!()
+        }
+    }
+
+    fn roll(system: DiceSystem, input: &str, draws: &[(u64, u64)]) -> Resolution {
+        let test = match system.parse(input) {
+            Some(test) => test,
+            None => panic!("`{}` should parse", input),
+        };
+        let mut rng = Draws(draws.iter());
+        let res = resolve(&mut rng, test);
+        assert!(rng.0.next().is_none(), "every value should be drawn");
+        res
+    }
+
+    fn d6(faces: &[u64]) -> Vec<(u64, u64)> {
+        faces.iter().map(|face| (face - 1, 6)).collect()
+    }
+
+    #[test]
+    fn dice_terms() {
+        let generic = |input| DiceSystem::Generic.parse(input).is_some();
+        assert!(generic("2d6+3"));
+        assert!(generic("d20"));
+        assert!(generic("4d6kh3-1d4kl1"));
+        assert!(generic("100d1000"));
+        assert!(!generic("0d6"));
+        assert!(!generic("101d6"));
+        assert!(!generic("1d1001"));
+        assert!(!generic("1d1!"));
+        assert!(!generic("2d6kh3"));
+        assert!(generic("2d6-1000000"));
+        assert!(!generic("2d6-9223372036854775808"));
+        assert!(!generic("18446744073709551616"));
+    }
+
+    #[test]
+    fn totals() {
+        let res = roll(DiceSystem::Generic, "2d6+3-d4", &[(1, 6), (4, 6), (2, 4)]);
+        assert_eq!(res.summary, "Total : 7.");
+        assert_eq!(res.details, "2d6 : 2, 5 = **7**\n1d4 : 3 = **3**");
+    }
+
+    #[test]
+    fn kept_dice() {
+        let res = roll(DiceSystem::Generic, "4d6kh3", &d6(&[1, 5, 3, 6]));
+        assert_eq!(res.summary, "Total : 14.");
+        assert_eq!(res.details, "4d6kh3 : ~~1~~, 5, 3, 6 = **14**");
+        let res = roll(DiceSystem::Generic, "2d6kl1", &d6(&[4, 2]));
+        assert_eq!(res.summary, "Total : 2.");
+    }
+
+    #[test]
+    fn explosions() {
+        let res = roll(DiceSystem::Generic, "3d6!", &d6(&[6, 6, 2, 3, 1]));
+        assert_eq!(res.summary, "Total : 18.");
+        assert_eq!(res.details, "3d6! : 6+6+2, 3, 1 = **18**");
+        // a chain stops after its last allowed explosion
+        let res = roll(DiceSystem::Generic, "1d6!", &d6(&[6; MAX_EXPLOSIONS + 1]));
+        assert_eq!(
+            res.summary,
+            format!("Total : {}.", 6 * (MAX_EXPLOSIONS + 1))
+        );
+    }
+
+    #[test]
+    fn fate_rolls() {
+        let fate = |input| match fate_roll(input) {
+            Ok(("", roll)) => Some((roll.dice, roll.modifier)),
+            _ => None,
+        };
+        assert_eq!(fate("4dF+2"), Some((4, 2)));
+        assert_eq!(fate("dF-1"), Some((FATE_DICE, -1)));
+        assert_eq!(fate("-3"), Some((FATE_DICE, -3)));
+        assert_eq!(fate("2"), Some((FATE_DICE, 2)));
+        assert_eq!(fate("-9223372036854775808"), None);
+        let res = roll(DiceSystem::Fate, "+2", &[(2, 3), (0, 3), (1, 3), (2, 3)]);
+        assert_eq!(res.summary, "Résultat : +3, Bon.");
+        assert_eq!(res.details, "Dés : + − ▫ + | modificateur : +2.");
+        let res = roll(DiceSystem::Fate, "2dF-4", &[(0, 3), (0, 3)]);
+        assert_eq!(res.summary, "Résultat : -6, pire que Terrible.");
+        assert_eq!(fate_rank(10), "Légendaire et plus");
+    }
+
+    #[test]
+    fn percentile_rolls() {
+        let percentile = |input| match percentile_roll(input) {
+            Ok(("", roll)) => Some((roll.target, roll.extra_tens)),
+            _ => None,
+        };
+        assert_eq!(percentile("d100"), Some((None, 0)));
+        assert_eq!(percentile("45b2"), Some((Some(45), 2)));
+        assert_eq!(percentile("45p1"), Some((Some(45), -1)));
+        assert_eq!(percentile("45b3"), None);
+        // units first, then each ten; a penalty keeps the worst result
+        let res = roll(DiceSystem::Percentile, "45p1", &[(0, 10), (2, 10), (5, 10)]);
+        assert_eq!(res.summary, "Échec (50/45).");
+        assert_eq!(res.details, "Dizaines : 20, 50 | unités : 0.");
+        let res = roll(DiceSystem::Percentile, "45b1", &[(0, 10), (2, 10), (5, 10)]);
+        assert_eq!(res.summary, "Réussite majeure (20/45).");
+        let res = roll(DiceSystem::Percentile, "d100", &[(0, 10), (0, 10)]);
+        assert_eq!(res.summary, "Résultat : 100.");
+    }
+
+    #[test]
+    fn percentile_ranks() {
+        assert_eq!(percentile_rank(1, 10), "Réussite critique");
+        assert_eq!(percentile_rank(100, 99), "Maladresse");
+        assert_eq!(percentile_rank(96, 49), "Maladresse");
+        assert_eq!(percentile_rank(96, 99), "Réussite");
+        assert_eq!(percentile_rank(10, 50), "Réussite extrême");
+        assert_eq!(percentile_rank(11, 50), "Réussite majeure");
+        assert_eq!(percentile_rank(25, 50), "Réussite majeure");
+        assert_eq!(percentile_rank(50, 50), "Réussite");
+        assert_eq!(percentile_rank(51, 50), "Échec");
+    }
+}
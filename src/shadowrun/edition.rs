@@ -0,0 +1,157 @@
+use crate::{error::ARes, store, utils::clap_name};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context, framework::standard::Args, model::channel::Message, model::id::ChannelId,
+};
+use sparky_macros::cmd;
+use std::collections::{HashMap, HashSet};
+
+const STORE: &str = "editions";
+const INLINE_STORE: &str = "inline";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Edition {
+    Sr5,
+    Sr6,
+}
+
+impl Edition {
+    pub fn from_number(number: &str) -> Option<Edition> {
+        match number {
+            "5" => Some(Edition::Sr5),
+            "6" => Some(Edition::Sr6),
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> u8 {
+        match self {
+            Edition::Sr5 => 5,
+            Edition::Sr6 => 6,
+        }
+    }
+
+    // more than half the dice in 6th edition, at least half in 5th
+    pub fn glitch(self, ones: u64, dice: u64) -> bool {
+        match self {
+            Edition::Sr5 => ones >= dice / 2 + dice % 2,
+            Edition::Sr6 => ones > dice / 2,
+        }
+    }
+
+    // there are no limits in 6th edition
+    pub fn limit(self, limit: Option<u64>) -> Option<u64> {
+        match self {
+            Edition::Sr5 => limit,
+            Edition::Sr6 => None,
+        }
+    }
+}
+
+#[cmd]
+#[description = "Règles de Shadowrun utilisées par défaut dans ce salon par `sr roll` et \
+`sr odds`. Argument \
+optionnel : `5` ou `6`, sans argument affiche l’édition courante."]
+pub fn edition(ctx: &Context, msg: &Message, args: Args) {
+    let edition = match args.rest() {
+        "" => {
+            msg.reply(
+                ctx,
+                format!(
+                    "ce salon utilise les règles de la {}e édition.",
+                    channel_edition(msg.channel_id)?.number()
+                ),
+            )?;
+            return;
+        }
+        number => match Edition::from_number(number) {
+            Some(edition) => edition,
+            None => {
+                msg.reply(
+                    ctx,
+                    format!("utilisation : `{} 5` ou `6`.", clap_name("sr edition")),
+                )?;
+                return;
+            }
+        },
+    };
+    store::update(ctx, STORE, |editions: &mut HashMap<u64, Edition>| {
+        editions.insert(msg.channel_id.0, edition)
+    })?;
+    msg.reply(
+        ctx,
+        format!(
+            "ce salon utilise désormais les règles de la {}e édition.",
+            edition.number()
+        ),
+    )?;
+}
+
+pub fn channel_edition(channel_id: ChannelId) -> ARes<Edition> {
+    let editions: HashMap<u64, Edition> = store::load(STORE)?;
+    Ok(editions.get(&channel_id.0).cloned().unwrap_or(Edition::Sr5))
+}
+
+#[cmd]
+#[description = "Active ou désactive dans ce salon les jets écrits `[[EXPR]]` dans un message \
+ordinaire. Argument optionnel : `oui` ou `non`, sans argument affiche l’état courant."]
+pub fn inline(ctx: &Context, msg: &Message, args: Args) {
+    let enabled = match args.rest() {
+        "" => {
+            msg.reply(
+                ctx,
+                if inline_enabled(msg.channel_id)? {
+                    "les jets `[[EXPR]]` sont activés dans ce salon."
+                } else {
+                    "les jets `[[EXPR]]` sont désactivés dans ce salon."
+                },
+            )?;
+            return;
+        }
+        "oui" => true,
+        "non" => false,
+        _ => {
+            msg.reply(
+                ctx,
+                format!("utilisation : `{} oui` ou `non`.", clap_name("sr inline")),
+            )?;
+            return;
+        }
+    };
+    store::update(ctx, INLINE_STORE, |channels: &mut HashSet<u64>| {
+        if enabled {
+            channels.insert(msg.channel_id.0)
+        } else {
+            channels.remove(&msg.channel_id.0)
+        }
+    })?;
+    msg.reply(
+        ctx,
+        if enabled {
+            "les jets `[[EXPR]]` sont désormais activés dans ce salon."
+        } else {
+            "les jets `[[EXPR]]` sont désormais désactivés dans ce salon."
+        },
+    )?;
+}
+
+pub fn inline_enabled(channel_id: ChannelId) -> ARes<bool> {
+    let channels: HashSet<u64> = store::load(INLINE_STORE)?;
+    Ok(channels.contains(&channel_id.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glitches_and_limits() {
+        assert!(Edition::Sr5.glitch(2, 4));
+        assert!(!Edition::Sr6.glitch(2, 4));
+        assert!(Edition::Sr6.glitch(3, 4));
+        assert!(Edition::Sr5.glitch(2, 3));
+        assert!(!Edition::Sr6.glitch(1, 3));
+        assert_eq!(Edition::Sr5.limit(Some(4)), Some(4));
+        assert_eq!(Edition::Sr6.limit(Some(4)), None);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{
+    date::TZ_DEFAULT,
+    error::AVoid,
+    shadowrun::{
+        edition::Edition,
+        roll::{replay, Resolution, RollSetup, ShadowrunRoll},
+    },
+    store,
+    utils::clap_name,
+};
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context, framework::standard::Args, model::channel::Message, model::id::UserId,
+    utils::MessageBuilder,
+};
+use sparky_macros::cmd;
+
+const STORE: &str = "history";
+const MAX_RECORDS: usize = 2000;
+const DEFAULT_SHOWN: usize = 10;
+const MAX_SHOWN: usize = 15;
+// beyond this many standard deviations from the expected hits, luck is worth a comment
+const NOTABLE_DEVIATION: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordedRoll {
+    pub user: u64,
+    pub channel: u64,
+    pub time: i64,
+    pub expr: String,
+    pub summary: String,
+    pub hits: Option<u64>,
+    pub glitches: u64,
+    pub dice: Vec<Vec<u8>>,
+    pub edition: Edition,
+    #[serde(default)]
+    pub expanded: String,
+    #[serde(default)]
+    pub setup: Option<RollSetup>,
+    #[serde(default)]
+    pub secret: bool,
+    #[serde(default)]
+    pub second_chance: bool,
+}
+
+// oldest first, the oldest being dropped past `MAX_RECORDS`
+type History = Vec<RecordedRoll>;
+
+// the rolls of a message are recorded together, in order, so as to be replayed; secret ones
+// only count in the statistics
+pub fn record(
+    ctx: &Context,
+    msg: &Message,
+    setup: &RollSetup,
+    exprs: &[String],
+    resolutions: &[(&str, Resolution)],
+    secret: bool,
+) -> AVoid {
+    let time = Utc::now().timestamp();
+    let records = resolutions
+        .iter()
+        .zip(exprs)
+        .map(|((expr, res), expanded)| RecordedRoll {
+            user: msg.author.id.0,
+            channel: msg.channel_id.0,
+            time,
+            expr: (*expr).to_owned(),
+            summary: res.summary.clone(),
+            hits: res.hits,
+            glitches: res.rolls.iter().filter(|roll| roll.glitch).count() as u64,
+            dice: res.rolls.iter().map(|roll| roll.all.clone()).collect(),
+            edition: setup.edition,
+            expanded: expanded.clone(),
+            setup: Some(*setup),
+            secret,
+            second_chance: false,
+        });
+    store::update(ctx, STORE, |history: &mut History| {
+        history.extend(records);
+        if history.len() > MAX_RECORDS {
+            history.drain(..history.len() - MAX_RECORDS);
+        }
+    })?;
+    Ok(())
+}
+
+// a second chance replaces the dice of the roll it was used on
+pub fn record_second_chance(
+    ctx: &Context,
+    seed: u32,
+    user: u64,
+    res: &ShadowrunRoll,
+    summary: &str,
+) -> AVoid {
+    store::update(ctx, STORE, |history: &mut History| {
+        let recorded = history
+            .iter_mut()
+            .rev()
+            .find(|roll| roll.setup.map(|setup| setup.seed) == Some(seed) && roll.user == user);
+        if let Some(roll) = recorded {
+            roll.summary = summary.to_owned();
+            roll.hits = Some(res.hits);
+            roll.glitches = res.glitch as u64;
+            roll.dice = vec![res.all.clone()];
+            roll.second_chance = true;
+        }
+    })?;
+    Ok(())
+}
+
+#[cmd]
+#[description = "Derniers jets publics de ce salon. Argument optionnel : nombre de jets affichés, \
+10 par défaut, 15 au plus."]
+pub fn history(ctx: &Context, msg: &Message, args: Args) {
+    let shown = match args.rest() {
+        "" => DEFAULT_SHOWN,
+        count => match count.parse::<usize>() {
+            Ok(count) if (1..=MAX_SHOWN).contains(&count) => count,
+            _ => {
+                msg.reply(
+                    ctx,
+                    format!(
+                        "utilisation : `{} N`, N entre 1 et {}.",
+                        clap_name("sr history"),
+                        MAX_SHOWN
+                    ),
+                )?;
+                return;
+            }
+        },
+    };
+    let history: History = store::load(STORE)?;
+    let recent: Vec<&RecordedRoll> = history
+        .iter()
+        .rev()
+        .filter(|roll| roll.channel == msg.channel_id.0 && !roll.secret)
+        .take(shown)
+        .collect();
+    if recent.is_empty() {
+        msg.reply(ctx, "aucun jet enregistré dans ce salon.")?;
+        return;
+    }
+    let mut mb = MessageBuilder::new();
+    for roll in recent.iter().rev() {
+        mb.push_mono(
+            Utc.timestamp(roll.time, 0)
+                .with_timezone(&TZ_DEFAULT)
+                .format("%d/%m %H:%M"),
+        )
+        .push(" ")
+        .mention(&UserId(roll.user))
+        .push(" ")
+        .push_mono_safe(&roll.expr)
+        .push(" – ")
+        .push_line(&roll.summary);
+    }
+    msg.channel_id.send_message(ctx, |m| {
+        m.embed(|e| {
+            e.title(format!("{} derniers jets du salon", recent.len()))
+                .description(mb.build())
+        })
+    })?;
+}
+
+#[cmd]
+#[description = "Compare les réussites et complications d’un joueur à celles attendues pour ses \
+réserves. Argument optionnel : mention du joueur, par défaut l’auteur."]
+pub fn stats(ctx: &Context, msg: &Message) {
+    let user = msg.mentions.first().unwrap_or(&msg.author);
+    let history: History = store::load(STORE)?;
+    let rolls: Vec<&RecordedRoll> = history
+        .iter()
+        .filter(|roll| roll.user == user.id.0 && !roll.dice.is_empty())
+        .collect();
+    if rolls.is_empty() {
+        msg.reply(ctx, "aucun jet de Shadowrun enregistré pour ce joueur.")?;
+        return;
+    }
+    let mut dice = 0;
+    let mut hits = 0;
+    let mut glitches = 0;
+    let mut expected_glitches = 0.0;
+    for roll in &rolls {
+        glitches += roll.glitches;
+        for all in &roll.dice {
+            dice += all.len() as u64;
+            hits += all.iter().filter(|&&single| single >= 5).count() as u64;
+            expected_glitches += glitch_odds(all.len() as u64, roll.edition);
+        }
+    }
+    // each die is a hit with a probability of 1/3
+    let expected_hits = dice as f64 / 3.0;
+    let deviation = (hits as f64 - expected_hits) / (dice as f64 * 2.0 / 9.0).sqrt();
+    let verdict = if deviation <= -NOTABLE_DEVIATION {
+        "Des dés maudits !"
+    } else if deviation >= NOTABLE_DEVIATION {
+        "Une chance insolente !"
+    } else {
+        "Rien d’anormal."
+    };
+    let details = format!(
+        "{} jets, {} dés lancés.\nRéussites : {} pour {:.1} attendues.\n\
+        Complications : {} pour {:.1} attendues.\n{}",
+        rolls.len(),
+        dice,
+        hits,
+        expected_hits,
+        glitches,
+        expected_glitches,
+        verdict
+    );
+    msg.channel_id.send_message(ctx, |m| {
+        m.embed(|e| {
+            e.title(format!("Statistiques de {}", user.name))
+                .description(details)
+        })
+    })?;
+}
+
+#[cmd]
+#[description = "Rejoue un jet à partir de son identifiant et vérifie que ses dés sont bien \
+ceux tirés, sans dévoiler un jet secret. Argument : identifiant du jet."]
+pub fn verify(ctx: &Context, msg: &Message, args: Args) {
+    let id = args.rest().trim().to_lowercase();
+    let seed = match u32::from_str_radix(&id, 16) {
+        Ok(seed) => seed,
+        Err(_) => {
+            msg.reply(
+                ctx,
+                format!("utilisation : `{} ID`.", clap_name("sr verify")),
+            )?;
+            return;
+        }
+    };
+    let history: History = store::load(STORE)?;
+    let same_message = |a: &RecordedRoll, b: &RecordedRoll| {
+        a.setup.map(|setup| setup.seed) == b.setup.map(|setup| setup.seed)
+            && a.user == b.user
+            && a.time == b.time
+    };
+    // the latest message with this seed, should another share it
+    let end = match history
+        .iter()
+        .rposition(|roll| roll.setup.map(|setup| setup.seed) == Some(seed))
+    {
+        Some(end) => end,
+        None => {
+            msg.reply(
+                ctx,
+                format!("aucun jet enregistré sous l’identifiant `{}`.", id),
+            )?;
+            return;
+        }
+    };
+    let start = history[..end]
+        .iter()
+        .rposition(|roll| !same_message(roll, &history[end]))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let rolls = &history[start..=end];
+    let exprs: Vec<&str> = rolls.iter().map(|roll| roll.expanded.as_str()).collect();
+    let replayed = match rolls[0]
+        .setup
+        .as_ref()
+        .and_then(|setup| replay(setup, &exprs))
+    {
+        Some(replayed) => replayed,
+        None => {
+            msg.reply(ctx, format!("le jet `{}` ne peut pas être rejoué.", id))?;
+            return;
+        }
+    };
+    let mut mb = MessageBuilder::new();
+    mb.push("Jet de ")
+        .mention(&UserId(rolls[0].user))
+        .push_line(format!(
+            ", le {}.",
+            Utc.timestamp(rolls[0].time, 0)
+                .with_timezone(&TZ_DEFAULT)
+                .format("%d/%m à %H:%M")
+        ));
+    let mut all_match = true;
+    for (roll, res) in rolls.iter().zip(replayed) {
+        // only the dice are compared, drain and soak being replayed as bare pools
+        let dice: Vec<Vec<u8>> = match (&res.second_chance, roll.second_chance) {
+            (Some(original), true) => vec![original.reroll(seed).1.all],
+            _ => res.rolls.into_iter().map(|roll| roll.all).collect(),
+        };
+        let matches = dice == roll.dice;
+        all_match &= matches;
+        mb.push(if matches { "✅ " } else { "❌ " });
+        // the dice of a secret roll are checked without being shown
+        if roll.secret {
+            mb.push_line("Jet secret");
+        } else {
+            mb.push_mono_safe(&roll.expr)
+                .push(if roll.second_chance {
+                    " – seconde chance – "
+                } else {
+                    " – "
+                })
+                .push_line(&roll.summary);
+        }
+    }
+    let title = if all_match {
+        format!("Jet `{}` vérifié : les dés correspondent.", id)
+    } else {
+        format!("Jet `{}` : les dés ne correspondent pas !", id)
+    };
+    msg.channel_id
+        .send_message(ctx, |m| m.embed(|e| e.title(title).description(mb.build())))?;
+}
+
+// probability of at least as many ones as the edition’s glitch rule requires
+fn glitch_odds(dice: u64, edition: Edition) -> f64 {
+    let mut odds = 0.0;
+    let mut ways = 1.0;
+    for ones in 0..=dice {
+        if ones > 0 {
+            ways = ways * (dice - ones + 1) as f64 / ones as f64;
+        }
+        if edition.glitch(ones, dice) {
+            odds +=
+                ways * (1.0 / 6.0f64).powi(ones as i32) * (5.0 / 6.0f64).powi((dice - ones) as i32);
+        }
+    }
+    odds
+}
@@ -0,0 +1,274 @@
+use crate::{
+    discord::{delete_command_ifp, reaction_is_own},
+    error::{ARes, AVoid},
+    help::{clap_bad_use, clap_help, clap_settings},
+    shadowrun::RUNNER,
+    state::{encode, extract, find_by_state, Embedded},
+    utils::clap_name,
+};
+use anyhow::{anyhow, bail};
+use clap::{App, Arg};
+use nom::{
+    branch::alt,
+    character::complete::{char, digit1},
+    combinator::{all_consuming, map_res, verify},
+    sequence::tuple,
+    IResult,
+};
+use rand::{
+    distributions::{Distribution, Uniform},
+    thread_rng, Rng,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    framework::standard::Args,
+    model::channel::ReactionType::Unicode,
+    model::channel::{Message, Reaction},
+    model::guild::Role,
+    model::id::UserId,
+    utils::MessageBuilder,
+};
+use sparky_macros::cmd;
+use std::str::FromStr;
+
+const PASS_COST: i64 = 10;
+const MAX_INIT_DICE: u64 = 5;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShadowrunInit {
+    pub turn: u64,
+    pub pass: u64,
+    pub combatants: Vec<Combatant>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Combatant {
+    pub user_raw_id: u64,
+    pub name: Option<String>,
+    pub base: u64,
+    pub dice: u64,
+    pub score: i64,
+}
+
+#[cmd]
+#[description = "Suivi d’initiative : crée un tableau ou y inscrit un jet.\n\
+***ILC :** appelez avec `--help` pour l’utilisation.*"]
+pub fn init(ctx: &Context, msg: &Message, args: Args) {
+    let app_name = clap_name("sr init");
+    let app = App::new(app_name.clone())
+        .about("Suivi d’initiative.")
+        .long_about(
+            "**Suivi d’initiative**\n\
+                Sans expression, crée un nouveau tableau d’initiative.\n\
+                Avec une expression `B+Nd6` (`B` base, `N` dés d’initiative), lance \
+                l’initiative et l’inscrit dans le dernier tableau.\n\
+                Sur le tableau : 🎲 relance son initiative, ⏩ passe d’initiative suivante \
+                (-10 ; nouveau tour de combat quand plus personne n’agit).",
+        )
+        .arg(Arg::with_name("EXPR").help("Initiative sous la forme `B+Nd6`."))
+        .arg(
+            Arg::with_name("name")
+                .short("n")
+                .takes_value(true)
+                .help("Nom d’un PNJ contrôlé par l’appelant."),
+        );
+    let app = clap_settings(app);
+    let args = match clap_help(ctx, msg, args, app)? {
+        Some(args) => args,
+        None => return,
+    };
+    let expr = match args.value_of("EXPR") {
+        Some(expr) => expr,
+        None => {
+            let mut tracker = msg.channel_id.send_message(ctx, |m| {
+                m.embed(|e| e.description("En préparation..."))
+                    .reactions(vec!["🎲", "⏩"])
+            })?;
+            let data = ShadowrunInit {
+                turn: 1,
+                pass: 1,
+                combatants: vec![],
+            };
+            refresh(ctx, &mut tracker, data)?;
+            return;
+        }
+    };
+    let (base, dice) = if let Ok(("", parsed)) = all_consuming(init_expr)(expr) {
+        parsed
+    } else {
+        clap_bad_use(ctx, msg, app_name)?;
+        return;
+    };
+    let (mut tracker, mut data) = last_tracker(ctx, msg)?;
+    let mut rng = thread_rng();
+    data.join(
+        &mut rng,
+        msg.author.id,
+        args.value_of("name").map(str::to_owned),
+        base,
+        dice,
+    );
+    refresh(ctx, &mut tracker, data)?;
+    delete_command_ifp(ctx, msg)?;
+}
+
+pub fn react(ctx: &Context, reaction: &Reaction) -> AVoid {
+    if reaction_is_own(ctx, reaction)? {
+        return Ok(());
+    }
+    let mut msg = reaction.message(ctx)?;
+    if let Some(Embedded::EShadowrunInit(mut data)) = extract(ctx, &msg) {
+        let mut rng = thread_rng();
+        match &reaction.emoji {
+            Unicode(emote) if emote == "🎲" => data.reroll(&mut rng, reaction.user_id),
+            Unicode(emote) if emote == "⏩" => data.next_pass(&mut rng),
+            _ => return Ok(()),
+        }
+        refresh(ctx, &mut msg, data)?;
+        // removing the reaction is best efforts: it only allows to use it again
+        let _ = reaction.delete(ctx);
+    }
+    Ok(())
+}
+
+impl ShadowrunInit {
+    fn join(
+        &mut self,
+        rng: &mut impl Rng,
+        user_id: UserId,
+        name: Option<String>,
+        base: u64,
+        dice: u64,
+    ) {
+        self.combatants
+            .retain(|c| !(c.user_raw_id == user_id.0 && c.name == name));
+        let mut combatant = Combatant {
+            user_raw_id: user_id.0,
+            name,
+            base,
+            dice,
+            score: 0,
+        };
+        combatant.roll(rng, self.pass);
+        self.combatants.push(combatant);
+        self.sort();
+    }
+
+    fn reroll(&mut self, rng: &mut impl Rng, user_id: UserId) {
+        let pass = self.pass;
+        for combatant in &mut self.combatants {
+            if combatant.user_raw_id == user_id.0 {
+                combatant.roll(rng, pass);
+            }
+        }
+        self.sort();
+    }
+
+    fn next_pass(&mut self, rng: &mut impl Rng) {
+        self.pass += 1;
+        for combatant in &mut self.combatants {
+            combatant.score -= PASS_COST;
+        }
+        if self.combatants.iter().all(|c| c.score <= 0) {
+            self.turn += 1;
+            self.pass = 1;
+            for combatant in &mut self.combatants {
+                combatant.roll(rng, 1);
+            }
+        }
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.combatants
+            .sort_by(|a, b| b.score.cmp(&a.score).then(b.base.cmp(&a.base)));
+    }
+}
+
+impl Combatant {
+    // a late roll takes the passes already elapsed into account
+    fn roll(&mut self, rng: &mut impl Rng, pass: u64) {
+        let d6 = Uniform::new_inclusive(1, 6);
+        let rolled: u64 = (0..self.dice).map(|_| d6.sample(rng)).sum();
+        self.score = (self.base + rolled) as i64 - PASS_COST * (pass as i64 - 1);
+    }
+
+    fn push_who(&self, mb: &mut MessageBuilder) {
+        let user_id = UserId(self.user_raw_id);
+        if let Some(name) = &self.name {
+            mb.push_bold(name).push(" (").mention(&user_id).push(")");
+        } else {
+            mb.mention(&user_id);
+        }
+    }
+}
+
+fn last_tracker(ctx: &Context, base: &Message) -> ARes<(Message, ShadowrunInit)> {
+    if let Ok((msg, Embedded::EShadowrunInit(data))) =
+        find_by_state(ctx, base, |d| matches!(d, Embedded::EShadowrunInit(_)))
+    {
+        Ok((msg, data))
+    } else {
+        base.reply(ctx, "je n’ai pas trouvé de tableau d’initiative.")?;
+        bail!("could not find initiative message")
+    }
+}
+
+fn refresh(ctx: &Context, msg: &mut Message, data: ShadowrunInit) -> AVoid {
+    let runner: Role = RUNNER
+        .to_role_cached(ctx)
+        .ok_or_else(|| anyhow!("no role"))?;
+    let mut description = MessageBuilder::new();
+    description
+        .push("Tour ")
+        .push_bold(data.turn)
+        .push(", passe ")
+        .push_bold(data.pass)
+        .push(".\n\n");
+    let (acting, waiting): (Vec<&Combatant>, Vec<&Combatant>) =
+        data.combatants.iter().partition(|c| c.score > 0);
+    if acting.is_empty() {
+        description.push_italic("Personne n’a encore lancé son initiative.\n");
+    }
+    for combatant in acting {
+        description.push_bold(combatant.score).push(" – ");
+        combatant.push_who(&mut description);
+        description.push(format!(" ({}+{}d6)\n", combatant.base, combatant.dice));
+    }
+    if !waiting.is_empty() {
+        description.push("\nHors passe : ");
+        let mut waiting_peek = waiting.iter().peekable();
+        while let Some(combatant) = waiting_peek.next() {
+            combatant.push_who(&mut description);
+            if waiting_peek.peek().is_some() {
+                description.push(", ");
+            }
+        }
+        description.push(".\n");
+    }
+    description.push("\n🎲 relancer son initiative, ⏩ passe suivante.");
+    let footer = encode(Embedded::EShadowrunInit(data))?;
+    msg.edit(ctx, |m| {
+        m.embed(|e| {
+            e.title("Shadowrun – Initiative")
+                .colour(runner.colour)
+                .description(description)
+                .footer(|f| f.text(footer))
+        })
+    })?;
+    Ok(())
+}
+
+fn init_expr(input: &str) -> IResult<&str, (u64, u64)> {
+    let (input, (base, _, dice, _, _)) = tuple((
+        map_res(digit1, u64::from_str),
+        char('+'),
+        verify(map_res(digit1, u64::from_str), |dice| {
+            (1..=MAX_INIT_DICE).contains(dice)
+        }),
+        alt((char('d'), char('D'))),
+        char('6'),
+    ))(input)?;
+    Ok((input, (base, dice)))
+}
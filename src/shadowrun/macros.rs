@@ -0,0 +1,267 @@
+use crate::{
+    error::ARes,
+    help::{clap_bad_use, clap_help, clap_settings},
+    shadowrun::{
+        character::active_character,
+        roll::{shadowrun_tests, KEYWORDS},
+    },
+    store,
+    utils::clap_name,
+};
+use clap::{App, Arg};
+use nom::{
+    bytes::complete::is_not,
+    character::complete::{char, digit1, one_of},
+    combinator::{opt, recognize},
+    multi::many0,
+    sequence::{delimited, tuple},
+    IResult,
+};
+use serenity::{
+    client::Context, framework::standard::Args, model::channel::Message, model::id::UserId,
+    utils::MessageBuilder,
+};
+use sparky_macros::cmd;
+use std::collections::{BTreeMap, HashMap};
+
+const STORE: &str = "macros";
+
+// user id to macro name to expression
+type Macros = HashMap<u64, BTreeMap<String, String>>;
+
+#[cmd]
+#[description = "Gère ses macros de jets de dés, utilisables dans `sr roll`.\n\
+***ILC :** appelez avec `--help` pour l’utilisation.*"]
+pub fn macros(ctx: &Context, msg: &Message, args: Args) {
+    let app_name = clap_name("sr macros");
+    let app = App::new(app_name.clone())
+        .about("Gère ses macros de jets de dés.")
+        .long_about(
+            "**Gère ses macros de jets de dés**\n\
+                Sans argument, liste ses macros.\n\
+                `NOM = EXPR` enregistre une expression de `sr roll` sous un nom. Elle s’utilise \
+                ensuite seule (`!r perception`) ou complétée de termes (`!r perception+2`).\n\
+                Un nom commence par une lettre et ne contient que des lettres, chiffres et `_`.",
+        )
+        .arg(
+            Arg::with_name("DEFINITION")
+                .multiple(true)
+                .help("Définition sous la forme `NOM = EXPR`."),
+        )
+        .arg(
+            Arg::with_name("delete")
+                .short("d")
+                .takes_value(true)
+                .help("Supprime la macro de ce nom."),
+        );
+    let app = clap_settings(app);
+    let args = match clap_help(ctx, msg, args, app)? {
+        Some(args) => args,
+        None => return,
+    };
+    if let Some(name) = args.value_of("delete") {
+        let removed = store::update(ctx, STORE, |macros: &mut Macros| {
+            macros
+                .get_mut(&msg.author.id.0)
+                .and_then(|user_macros| user_macros.remove(name))
+        })?;
+        if removed.is_some() {
+            msg.reply(ctx, format!("macro `{}` supprimée.", name))?;
+        } else {
+            msg.reply(ctx, format!("macro inconnue : `{}`.", name))?;
+        }
+        return;
+    }
+    let definition: String = match args.values_of("DEFINITION") {
+        Some(values) => values.collect::<Vec<&str>>().join(" "),
+        None => {
+            let user_macros = user_macros(msg.author.id)?;
+            if user_macros.is_empty() {
+                msg.reply(ctx, "aucune macro enregistrée.")?;
+                return;
+            }
+            let mut description = MessageBuilder::new();
+            for (name, expr) in &user_macros {
+                description
+                    .push_mono(name)
+                    .push(" = ")
+                    .push_mono(expr)
+                    .push("\n");
+            }
+            msg.channel_id.send_message(ctx, |m| {
+                m.embed(|e| {
+                    e.title(format!("Macros de {}", msg.author.name))
+                        .description(description)
+                })
+            })?;
+            return;
+        }
+    };
+    let mut parts = definition.splitn(2, '=');
+    let (name, expr) = match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+        (Some(name), Some(expr)) if is_name(name) && !KEYWORDS.contains(&name) => {
+            (name.to_owned(), expr.to_owned())
+        }
+        _ => {
+            clap_bad_use(ctx, msg, app_name)?;
+            return;
+        }
+    };
+    if !matches!(shadowrun_tests(&expr), Ok(("", _))) {
+        msg.reply(ctx, "l’expression n’est pas un jet valide.")?;
+        return;
+    }
+    store::update(ctx, STORE, |macros: &mut Macros| {
+        macros
+            .entry(msg.author.id.0)
+            .or_default()
+            .insert(name.clone(), expr)
+    })?;
+    msg.reply(ctx, format!("macro `{}` enregistrée.", name))?;
+}
+
+fn user_macros(user_id: UserId) -> ARes<BTreeMap<String, String>> {
+    let mut macros: Macros = store::load(STORE)?;
+    Ok(macros.remove(&user_id.0).unwrap_or_default())
+}
+
+// expands the user’s macros, then the values of their active character
+pub fn user_expand(user_id: UserId, input: &str) -> ARes<Result<String, String>> {
+    let user_macros = user_macros(user_id)?;
+    let character = active_character(user_id)?;
+    Ok(expand(input, &user_macros, |name| {
+        character.as_ref().and_then(|c| c.value(name))
+    }))
+}
+
+// a macro used in a series keeps its limit and threshold after the added terms, so that
+// `perception+2` with `perception = 9+2[5]` gives `9+2+2[5]`; an unknown name is returned
+fn expand(
+    input: &str,
+    user_macros: &BTreeMap<String, String>,
+    value: impl Fn(&str) -> Option<u64>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = input;
+    let mut after_alphanumeric = false;
+    while let Some(c) = rest.chars().next() {
+        if c == '{' {
+            // labels are left as written
+            let len = rest.find('}').map(|i| i + 1).unwrap_or(rest.len());
+            out.push_str(&rest[..len]);
+            after_alphanumeric = false;
+            rest = &rest[len..];
+        } else if !after_alphanumeric && is_name_start(c) {
+            let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            let (name, tail) = rest.split_at(len);
+            rest = tail;
+            if KEYWORDS.contains(&name) {
+                out.push_str(name);
+            } else if let Some(body) = user_macros.get(name) {
+                let (pool, body_rest) = body.split_at(recognized_len(pool_terms, body));
+                let (added, tail) = rest.split_at(recognized_len(added_terms, rest));
+                out.push_str(pool);
+                out.push_str(added);
+                out.push_str(body_rest);
+                rest = tail;
+            } else {
+                out.push_str(&value(name).ok_or_else(|| name.to_owned())?.to_string());
+            }
+        } else {
+            out.push(c);
+            after_alphanumeric = c.is_alphanumeric();
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok(out)
+}
+
+pub fn is_name(input: &str) -> bool {
+    let mut chars = input.chars();
+    chars.next().map(is_name_start).unwrap_or(false) && chars.all(is_name_char)
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn recognized_len(parser: impl Fn(&str) -> IResult<&str, &str>, input: &str) -> usize {
+    parser(input).map(|(_, found)| found.len()).unwrap_or(0)
+}
+
+fn pool_terms(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((digit1, opt(label), added_terms)))(input)
+}
+
+fn added_terms(input: &str) -> IResult<&str, &str> {
+    recognize(many0(tuple((one_of("+-"), digit1, opt(label)))))(input)
+}
+
+fn label(input: &str) -> IResult<&str, &str> {
+    recognize(delimited(char('{'), is_not("{}"), char('}')))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_with(input: &str, macros: &[(&str, &str)]) -> Result<String, String> {
+        let user_macros = macros
+            .iter()
+            .map(|(name, expr)| ((*name).to_owned(), (*expr).to_owned()))
+            .collect();
+        expand(input, &user_macros, |name| match name {
+            "agi" => Some(5),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn macros_keep_their_limit_and_threshold() {
+        let macros = [("perception", "9+2[5]")];
+        assert_eq!(expand_with("perception", &macros).unwrap(), "9+2[5]");
+        assert_eq!(expand_with("perception+2", &macros).unwrap(), "9+2+2[5]");
+        assert_eq!(
+            expand_with("perception-1+3", &macros).unwrap(),
+            "9+2-1+3[5]"
+        );
+        // the body of a macro is stored as written, its names expanded on use
+        assert_eq!(
+            expand_with("tir+1", &[("tir", "8+3[6](2)")]).unwrap(),
+            "8+3+1[6](2)"
+        );
+    }
+
+    #[test]
+    fn keywords_are_not_names() {
+        let macros = [("perception", "9+2[5]")];
+        assert_eq!(
+            expand_with("perception vs 6", &macros).unwrap(),
+            "9+2[5] vs 6"
+        );
+        assert_eq!(
+            expand_with("perception +team 4,5", &macros).unwrap(),
+            "9+2[5] +team 4,5"
+        );
+        assert_eq!(expand_with("agi+2 vs agi", &[]).unwrap(), "5+2 vs 5");
+    }
+
+    #[test]
+    fn labels_are_left_as_written() {
+        let macros = [("perception", "9{base}+2[5]")];
+        assert_eq!(
+            expand_with("perception+2{lunettes}", &macros).unwrap(),
+            "9{base}+2+2{lunettes}[5]"
+        );
+        assert_eq!(expand_with("8{agi mod}+2", &[]).unwrap(), "8{agi mod}+2");
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert_eq!(expand_with("log+2", &[]), Err("log".to_owned()));
+    }
+}
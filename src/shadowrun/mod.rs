@@ -1,10 +1,33 @@
+pub mod breakdown;
+pub mod character;
+pub mod condition;
 pub mod confirm;
+pub mod dice;
+pub mod edition;
+pub mod history;
+pub mod init;
+pub mod macros;
+pub mod odds;
 pub mod plan;
 pub mod remind;
+pub mod resist;
 pub mod roll;
+pub mod second_chance;
 
+use crate::error::ARes;
 use crate::shadowrun::{
-    confirm::CONFIRM_COMMAND, plan::PLAN_COMMAND, remind::REMIND_COMMAND, roll::ROLL_COMMAND,
+    character::{CHAR_GROUP, EDGE_COMMAND},
+    condition::{DMG_COMMAND, HEAL_COMMAND},
+    confirm::CONFIRM_COMMAND,
+    edition::{EDITION_COMMAND, INLINE_COMMAND},
+    history::{HISTORY_COMMAND, STATS_COMMAND, VERIFY_COMMAND},
+    init::INIT_COMMAND,
+    macros::MACROS_COMMAND,
+    odds::ODDS_COMMAND,
+    plan::PLAN_COMMAND,
+    remind::REMIND_COMMAND,
+    resist::{DRAIN_COMMAND, SOAK_COMMAND},
+    roll::ROLL_COMMAND,
 };
 use anyhow::{anyhow, Context as _, Error};
 use fehler::throws;
@@ -12,8 +35,12 @@ use serenity::{
     client::Context,
     framework::standard::macros::group,
     model::id::{RoleId, UserId},
-    model::{channel::Reaction, guild::Role},
+    model::{
+        channel::{Message, Reaction},
+        guild::Role,
+    },
 };
+use std::env;
 
 match_env! {
 pub const RUNNER: RoleId = match {
@@ -21,10 +48,22 @@ pub const RUNNER: RoleId = match {
     test => 679702431222726715,
 }}
 
+// the GM receives secret rolls and restores edge, the owner unless configured otherwise
+pub fn game_master() -> ARes<UserId> {
+    Ok(match env::var("SPARKY_GAME_MASTER") {
+        Ok(id) => UserId(id.parse().context("invalid SPARKY_GAME_MASTER")?),
+        Err(_) => crate::OWNER,
+    })
+}
+
 #[group]
 #[prefix = "sr"]
 #[description = "Commandes liées au jeu de rôles papier Shadowrun."]
-#[commands(plan, confirm, remind, roll)]
+#[commands(
+    plan, confirm, remind, roll, odds, init, macros, dmg, heal, edge, edition, inline, drain, soak,
+    history, stats, verify
+)]
+#[sub_groups(Char)]
 pub struct Shadowrun;
 
 #[throws]
@@ -33,6 +72,17 @@ pub fn shadowrun_reaction(ctx: &Context, reaction: &Reaction) {
     confirm::react(ctx, reaction).context("confirm")?;
 }
 
+#[throws]
+pub fn shadowrun_message(ctx: &Context, msg: &Message) {
+    roll::inline(ctx, msg).context("inline roll")?;
+}
+
+#[throws]
+pub fn shadowrun_reaction_add(ctx: &Context, reaction: &Reaction) {
+    init::react(ctx, reaction).context("init")?;
+    second_chance::react(ctx, reaction).context("second chance")?;
+}
+
 #[throws]
 pub fn runners(ctx: &Context) -> Vec<UserId> {
     let runner: Role = RUNNER
@@ -0,0 +1,647 @@
+use crate::{
+    help::{clap_help, clap_settings},
+    shadowrun::edition::{channel_edition, Edition},
+    shadowrun::roll::{
+        user_test, wound_modifier, ShadowrunExtended, ShadowrunOpposed, ShadowrunSimple,
+        ShadowrunTeam, ShadowrunTest,
+    },
+    utils::clap_name,
+};
+use anyhow::anyhow;
+use clap::{App, Arg};
+use serenity::{client::Context, framework::standard::Args, model::channel::Message};
+use sparky_macros::cmd;
+use std::cmp::min;
+
+const MAX_POOL: u64 = 60;
+const MAX_THRESHOLD: u64 = 200;
+const NEGLIGIBLE: f64 = 1e-12;
+const DISPLAY_CUTOFF: f64 = 0.0005;
+
+#[cmd]
+#[description = "Calcule les probabilités d’un jet sans lancer de dés.\n***ILC :** appelez \
+avec `--help` pour l’utilisation.*"]
+pub fn odds(ctx: &Context, msg: &Message, args: Args) {
+    let app = App::new(clap_name("sr odds"))
+        .about("Calcule les probabilités d’un jet.")
+        .long_about(
+            "**Calcule les probabilités d’un jet**\n\
+                Accepte les mêmes expressions que `sr roll` en mode Shadowrun. Les probabilités \
+                sont exactes : réussites après application de la limite, complication, échec \
+                critique et seuil atteint. Les 6 relancés grâce à la chance sont pris en compte. \
+                Les règles sont celles de l’édition du salon (`sr edition`), sauf option.",
+        )
+        .arg(
+            Arg::with_name("edition")
+                .short("e")
+                .long("edition")
+                .takes_value(true)
+                .possible_values(&["5", "6"])
+                .help("Édition des règles de Shadowrun, par défaut celle du salon."),
+        )
+        .arg(
+            Arg::with_name("EXPR")
+                .required(true)
+                .multiple(true)
+                .help("Expression de lancer de dés, cf. `sr roll`."),
+        );
+    let app = clap_settings(app);
+    let args = match clap_help(ctx, msg, args, app)? {
+        Some(args) => args,
+        None => return,
+    };
+    let edition = match args.value_of("edition").and_then(Edition::from_number) {
+        Some(edition) => edition,
+        None => channel_edition(msg.channel_id)?,
+    };
+    let (_, mut test, mut breakdown) = match user_test(
+        msg.author.id,
+        &args
+            .values_of("EXPR")
+            .ok_or_else(|| anyhow!("unreachable: no expr"))?
+            .collect::<Vec<&str>>()
+            .join(" "),
+        edition,
+    )? {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            msg.reply(ctx, error)?;
+            return;
+        }
+    };
+    if test.boosted() {
+        msg.reply(ctx, "calcul impossible avec les atouts de la 6e édition.")?;
+        return;
+    }
+    let wounds = wound_modifier(msg.author.id)?;
+    test.reduce_pool(wounds);
+    if !within_bounds(&test) {
+        msg.reply(
+            ctx,
+            format!(
+                "calcul impossible au-delà de {} dés ou d’un seuil de {}.",
+                MAX_POOL, MAX_THRESHOLD
+            ),
+        )?;
+        return;
+    }
+    let (summary, mut details) = describe(test, edition);
+    if wounds > 0 {
+        breakdown.push("blessures", -(wounds as i64));
+    }
+    details.insert_str(0, &breakdown.render());
+    msg.channel_id
+        .send_message(ctx, |m| m.embed(|e| e.title(summary).description(details)))?;
+}
+
+fn within_bounds(test: &ShadowrunTest) -> bool {
+    match test {
+        ShadowrunTest::Simple(ShadowrunSimple { pool, .. }) => *pool <= MAX_POOL,
+        ShadowrunTest::Extended(ShadowrunExtended {
+            pool, threshold, ..
+        }) => *pool <= MAX_POOL && *threshold <= MAX_THRESHOLD,
+        ShadowrunTest::Opposed(ShadowrunOpposed {
+            my_pool,
+            their_pool,
+            ..
+        }) => *my_pool <= MAX_POOL && *their_pool <= MAX_POOL,
+        ShadowrunTest::Team(ShadowrunTeam { leader, assistants }) => {
+            assistants.iter().all(|&pool| pool <= MAX_POOL)
+                && assistants
+                    .iter()
+                    .fold(leader.pool, |total, &pool| total.saturating_add(pool))
+                    <= MAX_POOL
+        }
+    }
+}
+
+fn describe(test: ShadowrunTest, edition: Edition) -> (String, String) {
+    match test {
+        ShadowrunTest::Simple(ShadowrunSimple {
+            pool,
+            limit,
+            threshold,
+            buy: true,
+            ..
+        }) => {
+            let hits = edition
+                .limit(limit)
+                .map_or(pool / 4, |limit| min(pool / 4, limit));
+            let summary = match threshold {
+                Some(threshold) if hits < threshold => "Réussite : 0 %".to_owned(),
+                Some(_) => "Réussite : 100 %".to_owned(),
+                None => format!("{} réussites achetées", hits),
+            };
+            (
+                summary,
+                "Réussites achetées, sans jet ni complication.".to_owned(),
+            )
+        }
+        ShadowrunTest::Simple(ShadowrunSimple {
+            pool,
+            edge,
+            limit,
+            threshold,
+            ..
+        }) => describe_simple(&RollOdds::new(pool, edge, limit, edition), threshold),
+        ShadowrunTest::Extended(extended) => {
+            let odds = ExtendedOdds::new(&extended, edition);
+            let summary = format!("Réussite : {}", fmt_percent(odds.success));
+            let mut details = format!(
+                "Échec critique : {}\nÉchec à force de complications : {}\n\
+                Échec faute de réserve : {}",
+                fmt_percent(odds.fumble),
+                fmt_percent(odds.glitched_below_one),
+                fmt_percent(odds.pool_exhausted)
+            );
+            if extended.max_duration.is_some() {
+                details.push_str(&format!(
+                    "\nÉchec faute de temps : {}",
+                    fmt_percent(odds.out_of_time)
+                ));
+            }
+            if odds.success > 0.0 {
+                details.push_str(&format!(
+                    "\nEn cas de réussite, {} intervalles en moyenne.",
+                    fmt_decimal(odds.mean_intervals)
+                ));
+            }
+            (summary, details)
+        }
+        ShadowrunTest::Opposed(ShadowrunOpposed {
+            my_pool,
+            my_edge,
+            my_limit,
+            their_pool,
+            their_edge,
+            their_limit,
+        }) => {
+            let mine = RollOdds::new(my_pool, my_edge, my_limit, edition);
+            let theirs = RollOdds::new(their_pool, their_edge, their_limit, edition);
+            let mut win = 0.0;
+            let mut tie = 0.0;
+            for my_hits in 0..mine.len() {
+                for their_hits in 0..theirs.len() {
+                    // a critical glitch of the active side is never a win
+                    let p = if my_hits == 0 {
+                        mine.clean[0]
+                    } else {
+                        mine.exactly(my_hits)
+                    } * theirs.exactly(their_hits);
+                    if my_hits > their_hits {
+                        win += p;
+                    } else if my_hits == their_hits {
+                        tie += p;
+                    }
+                }
+            }
+            let summary = format!("Victoire : {}", fmt_percent(win));
+            let details = format!(
+                "Égalité, l’opposant l’emporte : {}\n\
+                Réussites en moyenne : {} contre {}\n\
+                Complication : {} (actif), {} (opposé)\n\
+                Échec critique : {} (actif), {} (opposé)",
+                fmt_percent(tie),
+                fmt_decimal(mine.mean()),
+                fmt_decimal(theirs.mean()),
+                fmt_percent(mine.glitch()),
+                fmt_percent(theirs.glitch()),
+                fmt_percent(mine.fumble()),
+                fmt_percent(theirs.fumble())
+            );
+            (summary, details)
+        }
+        ShadowrunTest::Team(ShadowrunTeam { leader, assistants }) => {
+            let bonus = team_bonus(&assistants, edition);
+            let (summary, mut details) =
+                describe_simple(&RollOdds::team(&leader, &bonus, edition), leader.threshold);
+            let mean: f64 = bonus
+                .iter()
+                .enumerate()
+                .map(|(hits, p)| hits as f64 * p)
+                .sum();
+            details.insert_str(
+                0,
+                &format!("Bonus d’équipe en moyenne : {}\n", fmt_decimal(mean)),
+            );
+            (summary, details)
+        }
+    }
+}
+
+fn describe_simple(odds: &RollOdds, threshold: Option<u64>) -> (String, String) {
+    let summary = if let Some(threshold) = threshold {
+        format!("Réussite : {}", fmt_percent(odds.success(threshold)))
+    } else {
+        format!("{} réussites en moyenne", fmt_decimal(odds.mean()))
+    };
+    let mut details = format!(
+        "Complication : {}\nÉchec critique : {}\n\nRéussites – exactement | au moins :\n",
+        fmt_percent(odds.glitch()),
+        fmt_percent(odds.fumble())
+    );
+    for hits in 0..odds.len() {
+        if odds.at_least(hits) < DISPLAY_CUTOFF {
+            break;
+        }
+        details.push_str(&format!(
+            "**{}** : {} | {}\n",
+            hits,
+            fmt_percent(odds.exactly(hits)),
+            fmt_percent(odds.at_least(hits))
+        ));
+    }
+    (summary, details)
+}
+
+// probabilities of the total hits of the assistants, which are never limited
+fn team_bonus(assistants: &[u64], edition: Edition) -> Vec<f64> {
+    assistants.iter().fold(vec![1.0], |total, &pool| {
+        let odds = RollOdds::new(pool, false, None, edition);
+        let mut sum = vec![0.0; total.len() + odds.len() - 1];
+        for (hits, p) in total.iter().enumerate() {
+            for more in 0..odds.len() {
+                sum[hits + more] += p * odds.exactly(more);
+            }
+        }
+        sum
+    })
+}
+
+// probabilities indexed by the number of hits after the limit, split on glitching
+struct RollOdds {
+    clean: Vec<f64>,
+    glitched: Vec<f64>,
+    edition: Edition,
+}
+
+impl RollOdds {
+    fn new(pool: u64, edge: bool, limit: Option<u64>, edition: Edition) -> Self {
+        let mut odds = RollOdds {
+            clean: vec![],
+            glitched: vec![],
+            edition,
+        };
+        let limit = edition.limit(limit);
+        if edge {
+            // sixes follow a negative binomial law, the last die of each chain shows 1 to 5
+            let mut sixes = 0;
+            let mut weight = (5.0f64 / 6.0).powi(pool as i32);
+            let mut remaining = 1.0;
+            while remaining > NEGLIGIBLE && weight > 0.0 {
+                odds.add_faces(pool, sixes, weight, 1.0 / 5.0, 1.0 / 5.0, None);
+                remaining -= weight;
+                weight *= (pool + sixes) as f64 / (sixes + 1) as f64 / 6.0;
+                sixes += 1;
+            }
+        } else {
+            odds.add_faces(pool, 0, 1.0, 1.0 / 3.0, 1.0 / 6.0, limit);
+        }
+        odds
+    }
+
+    // the leader’s odds weighted by each possible bonus from the assistants
+    fn team(leader: &ShadowrunSimple, bonus: &[f64], edition: Edition) -> Self {
+        let mut odds = RollOdds {
+            clean: vec![],
+            glitched: vec![],
+            edition,
+        };
+        for (extra, weight) in bonus.iter().enumerate() {
+            let extra = extra as u64;
+            let partial = RollOdds::new(
+                leader.pool.saturating_add(extra),
+                leader.edge,
+                leader.limit.map(|limit| limit.saturating_add(extra)),
+                edition,
+            );
+            if odds.len() < partial.len() {
+                odds.clean.resize(partial.len(), 0.0);
+                odds.glitched.resize(partial.len(), 0.0);
+            }
+            for hits in 0..partial.len() {
+                odds.clean[hits] += weight * partial.clean[hits];
+                odds.glitched[hits] += weight * partial.glitched[hits];
+            }
+        }
+        odds
+    }
+
+    fn add_faces(
+        &mut self,
+        dice: u64,
+        sixes: u64,
+        weight: f64,
+        p_hit: f64,
+        p_one: f64,
+        limit: Option<u64>,
+    ) {
+        let ln_fact = ln_factorials(dice);
+        let p_other = 1.0 - p_hit - p_one;
+        let total = dice + sixes;
+        for hits in 0..=dice {
+            for ones in 0..=dice - hits {
+                let others = dice - hits - ones;
+                let p = weight
+                    * (ln_fact[dice as usize]
+                        - ln_fact[hits as usize]
+                        - ln_fact[ones as usize]
+                        - ln_fact[others as usize]
+                        + hits as f64 * p_hit.ln()
+                        + ones as f64 * p_one.ln()
+                        + others as f64 * p_other.ln())
+                    .exp();
+                let limited = match limit {
+                    Some(limit) => min(sixes + hits, limit),
+                    None => sixes + hits,
+                } as usize;
+                if self.clean.len() <= limited {
+                    self.clean.resize(limited + 1, 0.0);
+                    self.glitched.resize(limited + 1, 0.0);
+                }
+                if self.edition.glitch(ones, total) {
+                    self.glitched[limited] += p;
+                } else {
+                    self.clean[limited] += p;
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.clean.len()
+    }
+
+    fn exactly(&self, hits: usize) -> f64 {
+        self.clean[hits] + self.glitched[hits]
+    }
+
+    fn at_least(&self, hits: usize) -> f64 {
+        (hits..self.len()).map(|h| self.exactly(h)).sum()
+    }
+
+    fn success(&self, threshold: u64) -> f64 {
+        (threshold as usize..self.len())
+            .map(|h| {
+                if h == 0 {
+                    self.clean[0]
+                } else {
+                    self.exactly(h)
+                }
+            })
+            .sum()
+    }
+
+    fn mean(&self) -> f64 {
+        (0..self.len()).map(|h| h as f64 * self.exactly(h)).sum()
+    }
+
+    fn glitch(&self) -> f64 {
+        self.glitched.iter().sum()
+    }
+
+    fn fumble(&self) -> f64 {
+        self.glitched.first().cloned().unwrap_or(0.0)
+    }
+}
+
+struct ExtendedOdds {
+    success: f64,
+    fumble: f64,
+    glitched_below_one: f64,
+    pool_exhausted: f64,
+    out_of_time: f64,
+    mean_intervals: f64,
+}
+
+impl ExtendedOdds {
+    // follows the same steps as the extended test resolution, over all accumulated hits at once
+    fn new(test: &ShadowrunExtended, edition: Edition) -> Self {
+        let threshold = test.threshold as usize;
+        let mut odds = ExtendedOdds {
+            success: 0.0,
+            fumble: 0.0,
+            glitched_below_one: 0.0,
+            pool_exhausted: 0.0,
+            out_of_time: 0.0,
+            mean_intervals: 0.0,
+        };
+        let mut alive = vec![0.0; threshold.max(1)];
+        alive[0] = 1.0;
+        let rolls = match test.max_intervals() {
+            Some(max) => min(max, test.pool),
+            None => test.pool,
+        };
+        for interval in 0..rolls {
+            let roll = RollOdds::new(
+                test.pool - interval,
+                test.edge_number > interval,
+                test.limit,
+                edition,
+            );
+            let mut next = vec![0.0; alive.len()];
+            let mut succeeded = 0.0;
+            for (acc, &p_acc) in alive.iter().enumerate() {
+                if p_acc == 0.0 {
+                    continue;
+                }
+                for hits in 0..roll.len() {
+                    let mut reach = |from: usize, p: f64| {
+                        if from + hits >= threshold {
+                            succeeded += p;
+                        } else {
+                            next[from + hits] += p;
+                        }
+                    };
+                    reach(acc, p_acc * roll.clean[hits]);
+                    let p_glitch = p_acc * roll.glitched[hits];
+                    if hits == 0 {
+                        odds.fumble += p_glitch;
+                        continue;
+                    }
+                    for penalty in 1..=6 {
+                        match acc.checked_sub(penalty) {
+                            Some(from) if from > 0 => reach(from, p_glitch / 6.0),
+                            _ => odds.glitched_below_one += p_glitch / 6.0,
+                        }
+                    }
+                }
+            }
+            odds.success += succeeded;
+            odds.mean_intervals += succeeded * (interval + 1) as f64;
+            alive = next;
+        }
+        if rolls < test.pool {
+            odds.out_of_time = alive.iter().sum();
+        } else {
+            odds.pool_exhausted = alive.iter().sum();
+        }
+        if odds.success > 0.0 {
+            odds.mean_intervals /= odds.success;
+        }
+        odds
+    }
+}
+
+fn ln_factorials(n: u64) -> Vec<f64> {
+    let mut res = vec![0.0];
+    for i in 1..=n {
+        res.push(res[i as usize - 1] + (i as f64).ln());
+    }
+    res
+}
+
+fn fmt_percent(p: f64) -> String {
+    format!("{:.1} %", p * 100.0).replace('.', ",")
+}
+
+fn fmt_decimal(x: f64) -> String {
+    format!("{:.1}", x).replace('.', ",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} should be {}",
+            actual,
+            expected
+        );
+    }
+
+    fn total(odds: &RollOdds) -> f64 {
+        (0..odds.len()).map(|hits| odds.exactly(hits)).sum()
+    }
+
+    #[test]
+    fn distributions_sum_to_one() {
+        for pool in 0..=20 {
+            for &edge in &[false, true] {
+                for &limit in &[None, Some(0), Some(3)] {
+                    assert_close(total(&RollOdds::new(pool, edge, limit, Edition::Sr5)), 1.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn glitches() {
+        let one = RollOdds::new(1, false, None, Edition::Sr5);
+        assert_close(one.glitch(), 1.0 / 6.0);
+        assert_close(one.fumble(), 1.0 / 6.0);
+        let two = RollOdds::new(2, false, None, Edition::Sr5);
+        assert_close(two.glitch(), 11.0 / 36.0);
+        assert_close(two.fumble(), 7.0 / 36.0);
+        assert_close(two.success(1), 16.0 / 36.0 + 4.0 / 36.0);
+    }
+
+    #[test]
+    fn limits() {
+        let limited = RollOdds::new(2, false, Some(1), Edition::Sr5);
+        assert_eq!(limited.len(), 2);
+        assert_close(limited.exactly(1), 20.0 / 36.0);
+    }
+
+    #[test]
+    fn sixth_edition() {
+        let two = RollOdds::new(2, false, Some(1), Edition::Sr6);
+        assert_eq!(two.len(), 3);
+        assert_close(two.glitch(), 1.0 / 36.0);
+        assert_close(two.fumble(), 1.0 / 36.0);
+        // three ones out of four instead of two
+        assert_close(
+            RollOdds::new(4, false, None, Edition::Sr6).glitch(),
+            21.0 / 1296.0,
+        );
+        assert_close(
+            RollOdds::new(4, false, None, Edition::Sr5).glitch(),
+            171.0 / 1296.0,
+        );
+    }
+
+    #[test]
+    fn edge_explodes_sixes() {
+        let plain = RollOdds::new(1, false, Some(1), Edition::Sr5);
+        assert_close(plain.at_least(2), 0.0);
+        assert_close(plain.mean(), 1.0 / 3.0);
+        // a six followed by a hit, and a one after a six still glitches two dice
+        let edge = RollOdds::new(1, true, Some(1), Edition::Sr5);
+        assert_close(edge.exactly(0), 4.0 / 6.0);
+        assert_close(edge.at_least(2), 1.0 / 18.0);
+        assert_close(edge.mean(), 0.4);
+        assert_close(edge.glitch(), 7.0 / 36.0);
+    }
+
+    #[test]
+    fn team_bonuses() {
+        assert_eq!(team_bonus(&[], Edition::Sr5), vec![1.0]);
+        let one = team_bonus(&[1], Edition::Sr5);
+        assert_close(one[0], 2.0 / 3.0);
+        assert_close(one[1], 1.0 / 3.0);
+        let two = team_bonus(&[1, 1], Edition::Sr5);
+        assert_eq!(two.len(), 3);
+        assert_close(two[0], 4.0 / 9.0);
+        assert_close(two[1], 4.0 / 9.0);
+        assert_close(two[2], 1.0 / 9.0);
+    }
+
+    fn extended(pool: u64, max_duration: Option<Duration>) -> ExtendedOdds {
+        ExtendedOdds::new(
+            &ShadowrunExtended {
+                pool,
+                edge_number: 0,
+                limit: None,
+                threshold: 1,
+                interval: Some(Duration::hours(1)),
+                max_duration,
+            },
+            Edition::Sr5,
+        )
+    }
+
+    #[test]
+    fn extended_tests() {
+        let odds = extended(1, None);
+        assert_close(odds.success, 1.0 / 3.0);
+        assert_close(odds.fumble, 1.0 / 6.0);
+        assert_close(odds.pool_exhausted, 1.0 / 2.0);
+        assert_close(odds.mean_intervals, 1.0);
+        // a glitch on the first roll loses its hit, as nothing was accumulated yet
+        let odds = extended(2, None);
+        assert_close(odds.success, 19.0 / 36.0);
+        assert_close(odds.fumble, 8.5 / 36.0);
+        assert_close(odds.glitched_below_one, 4.0 / 36.0);
+        assert_close(odds.pool_exhausted, 4.5 / 36.0);
+        assert_close(odds.out_of_time, 0.0);
+        assert_close(odds.mean_intervals, 22.0 / 19.0);
+        let odds = extended(2, Some(Duration::hours(1)));
+        assert_close(odds.success, 16.0 / 36.0);
+        assert_close(odds.out_of_time, 9.0 / 36.0);
+        assert_close(odds.pool_exhausted, 0.0);
+    }
+
+    #[test]
+    fn oversized_teams() {
+        let team = |assistants: Vec<u64>| {
+            ShadowrunTest::Team(ShadowrunTeam {
+                leader: ShadowrunSimple {
+                    pool: 4,
+                    edge: false,
+                    limit: None,
+                    threshold: None,
+                    boosts: Vec::new(),
+                    buy: false,
+                },
+                assistants,
+            })
+        };
+        assert!(within_bounds(&team(vec![3, 4])));
+        assert!(!within_bounds(&team(vec![MAX_POOL, 1])));
+        assert!(!within_bounds(&team(vec![u64::MAX, 2])));
+        assert!(!within_bounds(&team(vec![u64::MAX, u64::MAX])));
+    }
+}
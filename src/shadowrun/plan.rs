@@ -1,83 +1,369 @@
 use crate::{
-    date::{fr_day_to_str, fr_month_to_str, fr_weekday_to_emote, fr_weekday_to_str, TZ_DEFAULT},
+    date::{
+        fr_day_to_str, fr_month_to_str, fr_weekday_from_shorthand, fr_weekday_to_emote,
+        fr_weekday_to_str, TZ_DEFAULT,
+    },
     discord::{pop_self, reaction_is_own},
-    error::AVoid,
-    shadowrun::{runners, RUNNER},
+    error::{ARes, AVoid},
+    help::{clap_bad_use, clap_help, clap_settings},
+    shadowrun::{
+        confirm::{self, default_time, read_participants},
+        runners, RUNNER,
+    },
     state::{encode, extract, Embedded},
     string::StrExt,
+    utils::clap_name,
 };
-use anyhow::anyhow;
-use chrono::{Datelike, Duration};
+use anyhow::{anyhow, Context as _};
+use chrono::{Date, Datelike, Duration, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use clap::{App, Arg};
 use serde::{Deserialize, Serialize};
 use serenity::{
     client::Context,
+    framework::standard::Args,
     model::channel::ReactionType::Unicode,
     model::channel::{Message, Reaction},
     model::guild::Role,
+    model::id::{MessageId, UserId},
+    model::user::User,
+    prelude::Mutex as SerenityMutex,
     utils::MessageBuilder,
 };
 use sparky_macros::cmd;
-use std::collections::HashSet;
+use std::{
+    cmp::{min, Reverse},
+    collections::HashSet,
+    sync::Arc,
+};
+
+const DEFAULT_LENGTH: u8 = 7;
+const MAX_LENGTH: u8 = 28;
+const WEEK: u8 = 7;
+const DEFAULT_QUORUM: u8 = 3;
+const MAX_BEST: usize = 3;
+
+pub const SLOT_NAMES: &[&str] = &["aprem", "soir", "nuit"];
+
+pub struct PlanLockKey;
+impl typemap::Key for PlanLockKey {
+    type Value = Arc<SerenityMutex<()>>;
+}
+
+// a plan is posted as one message per week and slot, so that weekday emotes stay unique in each;
+// its messages share the ID of the command that created them
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ShadowrunPlan {
+    pub origin_raw_id: u64,
+    pub start_timestamp: i64,
+    pub length: u8,
+    pub week: u8,
+    pub slot: Option<Slot>,
+    pub slots: u8,
+    pub quorum: u8,
+    pub closed: bool,
+}
+
+pub struct DayScore {
+    pub index: usize,
+    pub available: usize,
+    pub in_person: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slot {
+    Afternoon,
+    Evening,
+    Night,
+}
+
+impl Slot {
+    pub fn from_name(name: &str) -> Option<Slot> {
+        match name {
+            "aprem" => Some(Slot::Afternoon),
+            "soir" => Some(Slot::Evening),
+            "nuit" => Some(Slot::Night),
+            _ => None,
+        }
+    }
 
-#[derive(Serialize, Deserialize)]
-pub struct ShadowrunPlan;
+    pub fn label(self) -> &'static str {
+        match self {
+            Slot::Afternoon => "après-midi",
+            Slot::Evening => "soirée",
+            Slot::Night => "nuit",
+        }
+    }
+
+    pub fn default_time(self) -> NaiveTime {
+        match self {
+            Slot::Afternoon => NaiveTime::from_hms(14, 0, 0),
+            Slot::Evening => NaiveTime::from_hms(20, 0, 0),
+            Slot::Night => NaiveTime::from_hms(23, 0, 0),
+        }
+    }
+}
+
+impl ShadowrunPlan {
+    // a plan posted before its state was stored, over the week from its message, as then
+    pub fn legacy(msg: &Message) -> Self {
+        ShadowrunPlan {
+            origin_raw_id: msg.id.0,
+            start_timestamp: msg
+                .timestamp
+                .with_timezone(&TZ_DEFAULT)
+                .date()
+                .and_hms(12, 0, 0)
+                .timestamp(),
+            length: DEFAULT_LENGTH,
+            week: 0,
+            slot: None,
+            slots: 1,
+            quorum: DEFAULT_QUORUM,
+            closed: false,
+        }
+    }
+
+    pub fn weeks(self) -> u8 {
+        (self.length - 1) / WEEK + 1
+    }
+
+    pub fn parts(self) -> u8 {
+        self.weeks() * self.slots
+    }
+
+    pub fn first_day(self) -> Date<Tz> {
+        TZ_DEFAULT.timestamp(self.start_timestamp, 0).date()
+            + Duration::days((self.week * WEEK).into())
+    }
+
+    pub fn days(self) -> Vec<Date<Tz>> {
+        let first_day = self.first_day();
+        (0..min(WEEK, self.length - self.week * WEEK))
+            .map(|inc| first_day + Duration::days(inc.into()))
+            .collect()
+    }
+}
 
 #[cmd]
-#[description = "Crée un planning jusqu’à la semaine suivante."]
-pub fn plan(ctx: &Context, msg: &Message) {
-    let first_day = msg.timestamp.with_timezone(&TZ_DEFAULT).date();
+#[description = "Crée un planning, par défaut jusqu’à la semaine suivante.\n***ILC :** appelez \
+avec `--help` pour l’utilisation.*"]
+pub fn plan(ctx: &Context, msg: &Message, args: Args) {
+    let app_name = clap_name("sr plan");
+    let app = App::new(app_name.clone())
+        .about("Crée un planning, par défaut jusqu’à la semaine suivante.")
+        .long_about(
+            "**Crée un planning**\n\
+                Au-delà d’une semaine, le planning est découpé en un message par semaine, et \
+                en un message par créneau s’il y en a.\n\
+                Quand tous les runners ont répondu à tous ses messages, le planning crée de \
+                lui-même la confirmation de son meilleur jour et créneau, puis se clôt.",
+        )
+        .arg(
+            Arg::with_name("from")
+                .short("f")
+                .long("from")
+                .takes_value(true)
+                .help(
+                    "Premier jour : lettre du prochain jour de la semaine voulu (LAEJVSD) ou \
+                    date `JJ/MM`. Par défaut aujourd’hui.",
+                ),
+        )
+        .arg(
+            Arg::with_name("length")
+                .short("n")
+                .long("length")
+                .takes_value(true)
+                .help("Nombre de jours, 28 au plus. Par défaut 7."),
+        )
+        .arg(
+            Arg::with_name("slots")
+                .short("s")
+                .long("slots")
+                .takes_value(true)
+                .multiple(true)
+                .possible_values(SLOT_NAMES)
+                .help(
+                    "Créneaux sondés séparément, un message chacun : après-midi, soirée, \
+                    nuit. Par défaut la journée entière.",
+                ),
+        )
+        .arg(
+            Arg::with_name("quorum")
+                .short("q")
+                .long("quorum")
+                .takes_value(true)
+                .help("Nombre minimal de runners pour retenir un jour. Par défaut 3."),
+        );
+    let app = clap_settings(app);
+    let args = match clap_help(ctx, msg, args, app)? {
+        Some(args) => args,
+        None => return,
+    };
+    let today = msg.timestamp.with_timezone(&TZ_DEFAULT).date();
+    let start = match args.value_of("from") {
+        Some(from) => start_date(from, today),
+        None => Some(today),
+    };
+    let length = match args.value_of("length").map(str::parse::<u8>) {
+        Some(Ok(length)) if (1..=MAX_LENGTH).contains(&length) => Some(length),
+        Some(_) => None,
+        None => Some(DEFAULT_LENGTH),
+    };
+    let quorum = match args.value_of("quorum").map(str::parse::<u8>) {
+        Some(Ok(quorum)) if quorum > 0 => Some(quorum),
+        Some(_) => None,
+        None => Some(DEFAULT_QUORUM),
+    };
+    let (start, length, quorum) = match (start, length, quorum) {
+        (Some(start), Some(length), Some(quorum)) => (start, length, quorum),
+        _ => {
+            clap_bad_use(ctx, msg, app_name)?;
+            return;
+        }
+    };
+    let slots: Vec<Option<Slot>> = match args.values_of("slots") {
+        Some(names) => {
+            let mut slots = vec![];
+            for slot in names.map(Slot::from_name) {
+                if !slots.contains(&slot) {
+                    slots.push(slot);
+                }
+            }
+            slots
+        }
+        None => vec![None],
+    };
     let runners = runners(ctx)?;
-    let mut base = msg.channel_id.send_message(ctx, |m| {
-        m.content({
-            let mut mb = MessageBuilder::new();
-            for runner in &runners {
-                mb.mention(runner);
-                mb.push(" ");
+    let first = ShadowrunPlan {
+        origin_raw_id: msg.id.0,
+        start_timestamp: start.and_hms(12, 0, 0).timestamp(),
+        length,
+        week: 0,
+        slot: None,
+        slots: slots.len() as u8,
+        quorum,
+        closed: false,
+    };
+    let parts = (0..first.weeks())
+        .flat_map(|week| slots.iter().map(move |&slot| (week, slot)))
+        .enumerate();
+    for (i, (week, slot)) in parts {
+        let data = ShadowrunPlan {
+            week,
+            slot,
+            ..first
+        };
+        let mut base = msg.channel_id.send_message(ctx, |m| {
+            if i == 0 {
+                m.content({
+                    let mut mb = MessageBuilder::new();
+                    for runner in &runners {
+                        mb.mention(runner);
+                        mb.push(" ");
+                    }
+                    mb
+                });
             }
-            mb
+            m.embed(|e| e.description("En préparation...")).reactions(
+                data.days()
+                    .into_iter()
+                    .map(|day| fr_weekday_to_emote(day.weekday()))
+                    .chain(vec!["🚫", "💻"]),
+            )
+        })?;
+        let lock = plan_lock(ctx)?;
+        let _guard = lock.lock();
+        refresh(ctx, &mut base, data)?;
+    }
+}
+
+// the next such weekday after today, or a day and month in the coming year
+fn start_date(input: &str, today: Date<Tz>) -> Option<Date<Tz>> {
+    if let Ok(weekday) = fr_weekday_from_shorthand(input) {
+        let mut date = today.succ();
+        while date.weekday() != weekday {
+            date = date.succ();
+        }
+        return Some(date);
+    }
+    let mut parts = input.splitn(2, '/');
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let this_year = TZ_DEFAULT.ymd_opt(today.year(), month, day).single()?;
+    if this_year >= today {
+        Some(this_year)
+    } else {
+        TZ_DEFAULT.ymd_opt(today.year() + 1, month, day).single()
+    }
+}
+
+// days reaching the quorum, most runners first, then most runners in person, then earliest
+pub fn best_days(available: &[Vec<User>], online: &[User], quorum: u8) -> Vec<DayScore> {
+    let mut scores: Vec<DayScore> = available
+        .iter()
+        .enumerate()
+        .map(|(index, users)| DayScore {
+            index,
+            available: users.len(),
+            in_person: users.iter().filter(|user| !online.contains(user)).count(),
         })
-        .embed(|e| e.description("En préparation..."))
-        .reactions(
-            (0..=6)
-                .map(|inc| fr_weekday_to_emote((first_day + Duration::days(inc)).weekday()))
-                .chain(vec!["🚫", "💻"]),
+        .filter(|score| score.available >= quorum.into())
+        .collect();
+    scores.sort_by_key(|score| {
+        (
+            Reverse(score.available),
+            Reverse(score.in_person),
+            score.index,
         )
-    })?;
-    refresh(ctx, &mut base)?;
+    });
+    scores
 }
 
 pub fn react(ctx: &Context, reaction: &Reaction) -> AVoid {
     if reaction_is_own(ctx, reaction)? {
         return Ok(());
     }
-    let mut msg = reaction.message(ctx)?;
+    let msg = reaction.message(ctx)?;
     if let Some(Embedded::EShadowrunPlan(_)) = extract(ctx, &msg) {
-        refresh(ctx, &mut msg)?;
+        let lock = plan_lock(ctx)?;
+        let _guard = lock.lock();
+        // read again under the lock, as a concurrent reaction may have closed the plan meanwhile
+        let mut msg = msg.channel_id.message(ctx, msg.id)?;
+        if let Some(Embedded::EShadowrunPlan(data)) = extract(ctx, &msg) {
+            refresh(ctx, &mut msg, data)?;
+        }
     }
     Ok(())
 }
 
-fn refresh(ctx: &Context, msg: &mut Message) -> AVoid {
-    let runner: Role = RUNNER
-        .to_role_cached(ctx)
-        .ok_or_else(|| anyhow!("no role"))?;
-    let first_day = msg.timestamp.with_timezone(&TZ_DEFAULT).date();
-    let last_day = first_day + Duration::days(6);
-    let chan = msg
-        .channel_id
-        .to_channel(ctx)?
-        .guild()
-        .ok_or_else(|| anyhow!("cannot get chan"))?;
-    let guild = chan
+// held while a plan is refreshed, so that concurrent reactions confirm a plan only once
+fn plan_lock(ctx: &Context) -> ARes<Arc<SerenityMutex<()>>> {
+    Ok(ctx
+        .data
         .read()
-        .guild(ctx)
-        .ok_or_else(|| anyhow!("cannot get guild"))?;
-    let guild_id = guild.read().id;
+        .get::<PlanLockKey>()
+        .context("plan lock not in data")?
+        .clone())
+}
+
+#[derive(Clone)]
+struct Tally {
+    available: Vec<Vec<User>>,
+    online: Vec<User>,
+    exhaustive: bool,
+}
+
+fn read_tally(
+    ctx: &Context,
+    msg: &Message,
+    data: ShadowrunPlan,
+    runners: &[UserId],
+) -> ARes<Tally> {
     let online = msg.reaction_users(ctx, Unicode("💻".to_owned()), None, None)?;
     let mut available = vec![];
     let mut voted = HashSet::new();
-    let mut day = first_day;
-    for _ in 0..=6 {
+    for day in data.days() {
         let mut users = msg.reaction_users(
             ctx,
             Unicode(fr_weekday_to_emote(day.weekday()).to_owned()),
@@ -89,52 +375,208 @@ fn refresh(ctx: &Context, msg: &mut Message) -> AVoid {
             voted.insert(user.id);
         }
         available.push(users);
-        day = day.succ();
     }
     for user in msg.reaction_users(ctx, Unicode("🚫".to_owned()), None, None)? {
         voted.insert(user.id);
     }
+    Ok(Tally {
+        available,
+        online,
+        exhaustive: runners.iter().all(|id| voted.contains(id)),
+    })
+}
+
+fn refresh(ctx: &Context, msg: &mut Message, mut data: ShadowrunPlan) -> AVoid {
     let runners = runners(ctx)?;
-    let exhaustive = runners.iter().all(|id| voted.contains(id));
-    let data = encode(Embedded::EShadowrunPlan(ShadowrunPlan))?;
+    let tally = read_tally(ctx, msg, data, &runners)?;
+    if tally.exhaustive && !data.closed {
+        data.closed = close(ctx, msg, data, &tally, &runners)?;
+    }
+    render(ctx, msg, data, &tally, &runners)
+}
+
+// once every runner answered every message of a plan, confirms its best day and slot, then marks
+// all its messages as closed; false while the plan is incomplete or no day reaches the quorum
+fn close(
+    ctx: &Context,
+    msg: &Message,
+    data: ShadowrunPlan,
+    tally: &Tally,
+    runners: &[UserId],
+) -> ARes<bool> {
+    let mut parts = vec![(msg.clone(), data, tally.clone())];
+    if data.parts() > 1 {
+        let mut found = HashSet::new();
+        found.insert((data.week, data.slot));
+        let messages = msg
+            .channel_id
+            .messages(ctx, |r| r.after(MessageId(data.origin_raw_id)).limit(100))?;
+        for message in messages {
+            if let Some(Embedded::EShadowrunPlan(other)) = extract(ctx, &message) {
+                if other.origin_raw_id == data.origin_raw_id
+                    && found.insert((other.week, other.slot))
+                {
+                    let tally = read_tally(ctx, &message, other, runners)?;
+                    parts.push((message, other, tally));
+                }
+            }
+        }
+    }
+    if parts.iter().any(|(_, part, _)| part.closed) {
+        return Ok(true);
+    }
+    if parts.len() < data.parts().into() || parts.iter().any(|(_, _, tally)| !tally.exhaustive) {
+        return Ok(false);
+    }
+    let best = parts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (_, part, tally))| {
+            let days = part.days();
+            best_days(&tally.available, &tally.online, data.quorum)
+                .into_iter()
+                .map(move |score| (i, days[score.index], part.slot, score))
+        })
+        .min_by_key(|(_, date, slot, score)| {
+            (
+                Reverse(score.available),
+                Reverse(score.in_person),
+                *date,
+                *slot,
+            )
+        });
+    let (i, date, slot, score) = match best {
+        Some(best) => best,
+        None => return Ok(false),
+    };
+    // in person whenever the quorum holds without the 💻-only runners
+    let online = score.in_person < data.quorum.into();
+    let participants = read_participants(ctx, &parts[i].0, date.weekday(), online)?;
+    confirm::post(
+        ctx,
+        msg.channel_id,
+        date,
+        &participants,
+        online,
+        default_time(slot),
+        vec![],
+    )?;
+    for (mut message, part, tally) in parts.into_iter().skip(1) {
+        let part = ShadowrunPlan {
+            closed: true,
+            ..part
+        };
+        render(ctx, &mut message, part, &tally, runners)?;
+    }
+    Ok(true)
+}
+
+fn render(
+    ctx: &Context,
+    msg: &mut Message,
+    data: ShadowrunPlan,
+    tally: &Tally,
+    runners: &[UserId],
+) -> AVoid {
+    let runner: Role = RUNNER
+        .to_role_cached(ctx)
+        .ok_or_else(|| anyhow!("no role"))?;
+    let days = data.days();
+    let last_day = *days.last().ok_or_else(|| anyhow!("empty plan"))?;
+    let chan = msg
+        .channel_id
+        .to_channel(ctx)?
+        .guild()
+        .ok_or_else(|| anyhow!("cannot get chan"))?;
+    let guild = chan
+        .read()
+        .guild(ctx)
+        .ok_or_else(|| anyhow!("cannot get guild"))?;
+    let guild_id = guild.read().id;
+    let Tally {
+        available,
+        online,
+        exhaustive,
+    } = tally;
+    let mut title = "Shadowrun – Prochaine séance".to_owned();
+    if let Some(slot) = data.slot {
+        title.push_str(&format!(" – {}", slot.label()));
+    }
+    if data.weeks() > 1 {
+        title.push_str(&format!(" ({}/{})", data.week + 1, data.weeks()));
+    }
+    let best = best_days(available, online, data.quorum);
+    let best_field = if best.is_empty() {
+        format!("Aucun jour n’atteint le quorum de {} runners.", data.quorum)
+    } else {
+        best.iter()
+            .take(MAX_BEST)
+            .enumerate()
+            .map(|(rank, score)| {
+                let date = days[score.index];
+                format!(
+                    "{}. **{} {}** : {} runners{}",
+                    rank + 1,
+                    fr_weekday_to_str(date.weekday()).title_case(),
+                    fr_day_to_str(date),
+                    score.available,
+                    match score.available - score.in_person {
+                        0 => String::new(),
+                        remote => format!(", dont {} 💻", remote),
+                    }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    let top = best.first().map(|score| score.index);
+    let closed = data.closed;
+    let slot = data.slot;
+    let data = encode(Embedded::EShadowrunPlan(data))?;
     msg.edit(ctx, |m| {
         m.content({
             let mut mb = MessageBuilder::new();
-            for runner in &runners {
+            for runner in runners {
                 mb.mention(runner);
                 mb.push(" ");
             }
             mb
         })
         .embed(|e| {
-            e.title("Shadowrun – Prochaine séance")
+            e.title(title)
                 .colour(runner.colour)
                 .description({
                     let mut mb = MessageBuilder::new();
-                    if exhaustive {
+                    if *exhaustive {
                         mb.push("✅ ");
                     } else {
                         mb.push("⌛ ");
                     }
-                    mb.mention(&runner)
-                        .push(", vos disponibilités jusqu'au ")
+                    mb.mention(&runner).push(", vos disponibilités ");
+                    if let Some(slot) = slot {
+                        mb.push("(").push_bold(slot.label()).push(") ");
+                    }
+                    mb.push("jusqu'au ")
                         .push_bold(fr_day_to_str(last_day))
                         .push(" ")
                         .push_bold(fr_month_to_str(last_day))
                         .push(".\nMettre 💻 si disponible uniquement en ligne.\n")
                         .push("Pensez à 🚫 si pas de disponibilité de la semaine.");
+                    if closed {
+                        mb.push("\n🔒 Planning clos : la confirmation a été créée.");
+                    }
                     mb
                 })
-                .fields((0..=6).map(|inc| {
-                    let date = first_day + Duration::days(inc);
+                .fields(days.iter().enumerate().map(|(inc, &date)| {
                     (
                         format!(
-                            "{} {}",
+                            "{}{} {}",
+                            if top == Some(inc) { "⭐ " } else { "" },
                             fr_weekday_to_str(date.weekday()).title_case(),
                             fr_day_to_str(date)
                         ),
                         {
-                            let list = &available[inc as usize];
+                            let list = &available[inc];
                             if list.is_empty() {
                                 "\u{200b}".to_owned()
                             } else {
@@ -154,6 +596,7 @@ fn refresh(ctx: &Context, msg: &mut Message) -> AVoid {
                         true,
                     )
                 }))
+                .field("Meilleurs jours", best_field, false)
                 .footer(|f| f.text(data))
         })
     })?;
@@ -0,0 +1,213 @@
+use crate::{
+    error::{ARes, AVoid},
+    help::{clap_bad_use, clap_help, clap_settings},
+    shadowrun::{
+        breakdown::Breakdown,
+        character::active_character,
+        condition::damage_expr,
+        edition::channel_edition,
+        history,
+        macros::user_expand,
+        roll::{pool_value, setup_pool, Resolution, ShadowrunRoll},
+    },
+    utils::clap_name,
+};
+use anyhow::anyhow;
+use clap::{App, Arg};
+use nom::combinator::all_consuming;
+use serenity::{client::Context, framework::standard::Args, model::channel::Message};
+use sparky_macros::cmd;
+
+#[cmd]
+#[description = "Lance un test de résistance au drain.\n***ILC :** appelez avec `--help` pour \
+l’utilisation.*"]
+pub fn drain(ctx: &Context, msg: &Message, args: Args) {
+    let app_name = clap_name("sr drain");
+    let app = App::new(app_name.clone())
+        .about("Lance un test de résistance au drain.")
+        .long_about(
+            "**Lance un test de résistance au drain**\n\
+                Chaque réussite réduit le drain d’une case. Le drain est physique s’il dépasse \
+                la magie, lue sur le personnage actif à défaut d’option, étourdissant sinon.",
+        )
+        .arg(
+            Arg::with_name("POOL")
+                .required(true)
+                .help("Réserve de résistance, série de termes (`wil+log`)."),
+        )
+        .arg(Arg::with_name("DV").required(true).help("Valeur de drain."))
+        .arg(
+            Arg::with_name("magic")
+                .short("m")
+                .takes_value(true)
+                .help("Magie du lanceur."),
+        );
+    let app = clap_settings(app);
+    let args = match clap_help(ctx, msg, args, app)? {
+        Some(args) => args,
+        None => return,
+    };
+    let raw = args
+        .value_of("POOL")
+        .ok_or_else(|| anyhow!("unreachable: no pool"))?;
+    let value = args.value_of("DV").and_then(|value| value.parse().ok());
+    let magic = match args.value_of("magic") {
+        Some(magic) => magic.parse().ok(),
+        None => active_character(msg.author.id)?.and_then(|sheet| sheet.value("mag")),
+    };
+    let ((pool, breakdown), value) = match (expand_pool(msg, raw)?, value) {
+        (Ok(pool), Some(value)) => (pool, value),
+        (Err(error), _) => {
+            msg.reply(ctx, error)?;
+            return;
+        }
+        _ => {
+            clap_bad_use(ctx, msg, app_name)?;
+            return;
+        }
+    };
+    let physical = matches!(magic, Some(magic) if value > magic);
+    send_resistance(ctx, msg, raw, pool, (value, physical), "drain", breakdown)?;
+}
+
+#[cmd]
+#[description = "Lance un test de résistance aux dommages.\n***ILC :** appelez avec `--help` pour \
+l’utilisation.*"]
+pub fn soak(ctx: &Context, msg: &Message, args: Args) {
+    let app_name = clap_name("sr soak");
+    let app = App::new(app_name.clone())
+        .about("Lance un test de résistance aux dommages.")
+        .long_about(
+            "**Lance un test de résistance aux dommages**\n\
+                La pénétration d’armure s’ajoute à la réserve, puis chaque réussite réduit les \
+                dommages d’une case.",
+        )
+        .arg(
+            Arg::with_name("POOL")
+                .required(true)
+                .help("Réserve de résistance, série de termes (`bod+12`)."),
+        )
+        .arg(
+            Arg::with_name("DV")
+                .required(true)
+                .help("Valeur de dommages, `NP` (physiques) ou `NS` (étourdissants)."),
+        )
+        .arg(
+            Arg::with_name("AP")
+                .allow_hyphen_values(true)
+                .help("Pénétration d’armure, par exemple `-2`."),
+        );
+    let app = clap_settings(app);
+    let args = match clap_help(ctx, msg, args, app)? {
+        Some(args) => args,
+        None => return,
+    };
+    let raw = args
+        .value_of("POOL")
+        .ok_or_else(|| anyhow!("unreachable: no pool"))?;
+    let damage = args
+        .value_of("DV")
+        .and_then(|value| all_consuming(damage_expr)(value).ok());
+    let penetration = match args.value_of("AP").map(str::parse::<i64>) {
+        Some(Ok(penetration)) => penetration,
+        Some(Err(_)) => {
+            clap_bad_use(ctx, msg, app_name)?;
+            return;
+        }
+        None => 0,
+    };
+    let ((pool, mut breakdown), damage) = match (expand_pool(msg, raw)?, damage) {
+        (Ok(pool), Some((_, damage))) => (pool, damage),
+        (Err(error), _) => {
+            msg.reply(ctx, error)?;
+            return;
+        }
+        _ => {
+            clap_bad_use(ctx, msg, app_name)?;
+            return;
+        }
+    };
+    if penetration != 0 {
+        breakdown.push("pénétration d’armure", penetration);
+    }
+    let pool = (pool as i64 + penetration).max(0) as u64;
+    send_resistance(ctx, msg, raw, pool, damage, "dommages", breakdown)?;
+}
+
+fn expand_pool(msg: &Message, pool: &str) -> ARes<Result<(u64, Breakdown), String>> {
+    Ok(match user_expand(msg.author.id, pool)? {
+        Ok(expr) => pool_value(&expr)
+            .map(|pool| (pool, Breakdown::of_expr(&expr)))
+            .ok_or_else(|| "réserve incorrecte.".to_owned()),
+        Err(name) => Err(format!("terme inconnu : `{}`.", name)),
+    })
+}
+
+// recorded as a bare pool, so that `sr verify` replays its dice
+fn send_resistance(
+    ctx: &Context,
+    msg: &Message,
+    raw: &str,
+    pool: u64,
+    (value, physical): (u64, bool),
+    resisted: &str,
+    breakdown: Breakdown,
+) -> AVoid {
+    let edition = channel_edition(msg.channel_id)?;
+    let (setup, res) = setup_pool(edition, pool);
+    let summary = resistance_summary(&res, value, physical);
+    let details = format!(
+        "{}Détail du jet : {}\nRéussites : {} contre une valeur de {} de {}.\n\
+        Identifiant du jet : `{}`.",
+        breakdown.render(),
+        res.fmt_details(),
+        res.hits,
+        resisted,
+        value,
+        setup.id()
+    );
+    let expr = format!("{} {}", resisted, raw);
+    let resolution = Resolution {
+        summary: summary.clone(),
+        hits: Some(res.hits),
+        details: details.clone(),
+        second_chance: None,
+        rolls: vec![res],
+    };
+    history::record(
+        ctx,
+        msg,
+        &setup,
+        &[pool.to_string()],
+        &[(&expr, resolution)],
+        false,
+    )?;
+    msg.channel_id
+        .send_message(ctx, |m| m.embed(|e| e.title(summary).description(details)))?;
+    Ok(())
+}
+
+fn resistance_summary(res: &ShadowrunRoll, value: u64, physical: bool) -> String {
+    let left = value.saturating_sub(res.hits);
+    let outcome = if left == 0 {
+        "Tout est encaissé sans dommages".to_owned()
+    } else {
+        format!(
+            "{} case{} de dommages {}",
+            left,
+            if left > 1 { "s" } else { "" },
+            if physical {
+                "physiques"
+            } else {
+                "étourdissants"
+            }
+        )
+    };
+    if res.fumble() {
+        format!("Échec critique ! {}.", outcome)
+    } else if res.glitch {
+        format!("{} – complication !", outcome)
+    } else {
+        format!("{}.", outcome)
+    }
+}
@@ -1,8 +1,17 @@
-// TODO: work in progress
-#![allow(dead_code, unused_variables)]
-
 use crate::{
+    discord::delete_command_ifp,
+    error::{ARes, AVoid},
     help::{clap_bad_use, clap_help, clap_settings},
+    shadowrun::{
+        breakdown::Breakdown,
+        character::{active_character, update_active},
+        dice::{self, DiceSystem, DiceTest},
+        edition::{channel_edition, inline_enabled, Edition},
+        game_master, history,
+        macros::user_expand,
+        second_chance::{SecondChance, ShadowrunSecondChance},
+    },
+    state::{encode, Embedded},
     utils::clap_name,
 };
 use anyhow::anyhow;
@@ -10,19 +19,23 @@ use chrono::Duration;
 use clap::{App, Arg};
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{char, digit1},
-    combinator::{map, map_res, opt},
-    multi::fold_many0,
-    sequence::{delimited, tuple},
+    bytes::complete::{is_not, tag},
+    character::complete::{char, digit1, one_of, space0, space1},
+    combinator::{all_consuming, cut, map, map_res, opt},
+    error::{context, ErrorKind, ParseError},
+    multi::{fold_many0, many0, separated_nonempty_list},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 use rand::{
     distributions::{Distribution, Uniform},
-    thread_rng, Rng,
+    thread_rng, Rng, SeedableRng,
 };
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use serenity::{
-    client::Context, framework::standard::Args, model::channel::Message, utils::MessageBuilder,
+    builder::CreateEmbed, client::Context, framework::standard::Args, model::channel::Message,
+    model::id::UserId, utils::MessageBuilder,
 };
 use sparky_macros::cmd;
 use std::{
@@ -31,6 +44,23 @@ use std::{
     str::FromStr,
 };
 
+pub const TEAM: &str = "team";
+const PUSH: &str = "push";
+const REROLL: &str = "reroll";
+const ADD: &str = "add";
+const VERSUS: &str = "vs";
+pub const KEYWORDS: &[&str] = &[TEAM, PUSH, REROLL, ADD, VERSUS];
+const MAX_ROLLS: u64 = 20;
+const DURATION_UNITS: &[(i64, &str, &str)] = &[
+    (30 * 24 * 3600, "mois", "mois"),
+    (7 * 24 * 3600, "semaine", "semaines"),
+    (24 * 3600, "jour", "jours"),
+    (3600, "heure", "heures"),
+    (60, "minute", "minutes"),
+    (3, "tour de combat", "tours de combat"),
+    (1, "seconde", "secondes"),
+];
+
 #[cmd]
 #[description = "Lance des dés.\n***ILC :** appelez avec `--help` \
 pour l’utilisation.*"]
@@ -41,16 +71,89 @@ pub fn roll(ctx: &Context, msg: &Message, args: Args) {
         .long_about(
             "**Lance des dés**\n\
                 Par défaut en mode Shadowrun. D’autres sont accessibles par des options.\n\
-                En mode Shadowrun, trois formes sont supportées :\n\
+                En mode Shadowrun, quatre formes sont supportées :\n\
                 • `R*[L](S)` : **Jet simple.** `R` réserve, `*` chance utilisée, `L` limite, `S` \
-                seuil. Seul `R` obligatoire.\n\
-                • `R*C[L](S,T)` : **Jet étendu.** `C` nombre de chances utilisées, `T` intervalle \
-                (1tc, 1m,\n10m, 30m, 1h, 1j, 1S, 1M). Seuls `R` et `S,` (avec la virgule) \
+                seuil. Seul `R` obligatoire. `!` à la place de `*` achète les réussites, une pour \
+                4 dés, sans jet ni complication.\n\
+                • `R*C[L](S,T,D)` : **Jet étendu.** `C` nombre de chances utilisées, `T` \
+                intervalle (1tc, 1m,\n10m, 30m, 1h, 1j, 1S, 1M), `D` durée maximale (nombre \
+                suivi de tc, m, h, j, S ou M, comme `2h`). Seuls `R` et `S,` (avec la virgule) \
                 obligatoires.\n\
-                • `R1*[L1]-R2*[L2]` : **Jet opposé.** Seuls `R1` et `-R2` obligatoires.\n\
-                Toutes les valeurs sauf `T` peuvent être une série de termes.",
+                • `R1*[L1] vs R2*[L2]` : **Jet opposé.** Seuls `R1` et `vs R2` obligatoires.\n\
+                • `R*[L](S) +team A1,A2,…` : **Travail d’équipe.** Jet simple du meneur, `A1`, \
+                `A2`… réserves des assistants, dont les réussites s’ajoutent à la réserve et à \
+                la limite du meneur.\n\
+                En 6e édition, les limites sont ignorées et la chance se dépense en atouts \
+                nommés à la suite d’un jet simple : `+push` (4 points, les 6 explosent), \
+                `+reroll` (1 point, relance un dé sans réussite), `+add` (2 points, +1 sur un dé \
+                sans réussite). L’édition par défaut du salon se règle avec `sr edition`.\n\
+                Toutes les valeurs sauf `T` peuvent être une série de termes. Un terme suivi \
+                d’une étiquette entre accolades est détaillé dans le résultat, par exemple \
+                `8+2{smartlink}-3{couvert}`.\n\
+                Les macros (`sr macros`) et les valeurs du personnage actif (`sr char`) \
+                s’utilisent comme des termes.\n\
+                Plusieurs jets se séparent par `;`, et `EXPR xN` répète N fois un jet (20 jets \
+                au plus).\n\
+                Après un jet simple public et unique sans chance, 🔁 permet à son auteur \
+                d’utiliser une seconde chance.\n\
+                En secret, la commande est effacée si possible, le résultat et les erreurs sont \
+                envoyés en privé à son auteur, le résultat aussi au MJ, et le salon n’affiche que \
+                l’identifiant du jet. Avec l’identifiant d’un jet, public ou \
+                secret, `sr verify` le rejoue pour en vérifier les dés.\n\
+                Dans les salons qui les activent avec `sr inline`, chaque `[[EXPR]]` d’un \
+                message ordinaire est lancé en mode Shadowrun, par exemple \
+                `je tire [[10[4](2)]]`.\n\
+                Modes alternatifs :\n\
+                • `-g` : **Dés génériques.** Somme de termes `NdM` ou constantes, par exemple \
+                `2d6+3`. `!` fait exploser les dés au maximum (`3d10!`), `khX` et `klX` ne \
+                gardent que les X plus hauts ou plus bas (`4d6kh3`).\n\
+                • `-f` : **Fate.** `NdF±K`, par défaut 4 dés, ou seulement le modificateur \
+                (`+2`).\n\
+                • `-p` : **Pourcentage.** `S` seuil de compétence ou `d100` sans seuil, suivi de \
+                `bN` dés bonus ou `pN` dés malus (2 au plus), par exemple `60b1`.",
+        )
+        .arg(
+            Arg::with_name("edition")
+                .short("e")
+                .long("edition")
+                .takes_value(true)
+                .possible_values(&["5", "6"])
+                .help("Édition des règles de Shadowrun, par défaut celle du salon."),
+        )
+        .arg(
+            Arg::with_name("buy")
+                .short("b")
+                .long("buy")
+                .conflicts_with_all(&["generic", "fate", "percentile"])
+                .help("Achète les réussites des jets simples et d’équipe, comme `!`."),
+        )
+        .arg(
+            Arg::with_name("generic")
+                .short("g")
+                .long("generic")
+                .conflicts_with_all(&["fate", "percentile"])
+                .help("Mode dés génériques."),
+        )
+        .arg(
+            Arg::with_name("fate")
+                .short("f")
+                .long("fate")
+                .conflicts_with("percentile")
+                .help("Mode Fate."),
         )
-        .arg(Arg::with_name("EXPR").help(
+        .arg(
+            Arg::with_name("percentile")
+                .short("p")
+                .long("percentile")
+                .help("Mode pourcentage avec dés bonus et malus."),
+        )
+        .arg(
+            Arg::with_name("secret")
+                .short("s")
+                .long("secret")
+                .help("Jet secret, résultat envoyé en privé à son auteur et au MJ."),
+        )
+        .arg(Arg::with_name("EXPR").multiple(true).help(
             "Expression de lancer de dés. Cf. description (mode Shadowrun, par défaut)\n\
                 ou options (modes alternatifs).",
         ));
@@ -59,60 +162,608 @@ pub fn roll(ctx: &Context, msg: &Message, args: Args) {
         Some(args) => args,
         None => return,
     };
-    let test = if let Ok(("", test)) = shadowrun_tests(
-        args.value_of("EXPR")
-            .ok_or_else(|| anyhow!("unreachable: no expr"))?,
-    ) {
-        test
-    } else {
-        clap_bad_use(ctx, msg, app_name)?;
+    let secret = args.is_present("secret");
+    // the command itself would reveal what is rolled
+    if secret && msg.guild_id.is_some() {
+        delete_command_ifp(ctx, msg)?;
+    }
+    let raw = args
+        .values_of("EXPR")
+        .ok_or_else(|| anyhow!("unreachable: no expr"))?
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let parts = match split_rolls(&raw) {
+        Some(parts) => parts,
+        None => {
+            clap_bad_use(ctx, msg, app_name)?;
+            return;
+        }
+    };
+    let system = [
+        ("generic", DiceSystem::Generic),
+        ("fate", DiceSystem::Fate),
+        ("percentile", DiceSystem::Percentile),
+    ]
+    .iter()
+    .find(|(name, _)| args.is_present(name))
+    .map(|(_, system)| *system);
+    let edition = match args.value_of("edition").and_then(Edition::from_number) {
+        Some(edition) => edition,
+        None => channel_edition(msg.channel_id)?,
+    };
+    let mut tests = vec![];
+    for (part, count) in parts {
+        let (expr, test, breakdown) = if let Some(system) = system {
+            match system.parse(part) {
+                Some(test) => (part.to_owned(), AnyTest::Dice(test), Breakdown::default()),
+                None => {
+                    clap_bad_use(ctx, msg, app_name)?;
+                    return;
+                }
+            }
+        } else {
+            match user_test(msg.author.id, part, edition)? {
+                Ok((_, test, _)) if args.is_present("buy") && !test.can_buy() => {
+                    reply(
+                        ctx,
+                        msg,
+                        secret,
+                        "seuls les jets simples et d’équipe peuvent acheter leurs réussites.",
+                    )?;
+                    return;
+                }
+                Ok((expr, test, breakdown)) => (expr, AnyTest::Shadowrun(test), breakdown),
+                Err(error) => {
+                    reply(ctx, msg, secret, error)?;
+                    return;
+                }
+            }
+        };
+        for _ in 0..count {
+            tests.push((part, expr.clone(), test.clone(), breakdown.clone()));
+        }
+    }
+    let report = match roll_tests(
+        ctx,
+        msg,
+        system,
+        edition,
+        args.is_present("buy"),
+        tests,
+        secret,
+    )? {
+        Some(report) => report,
+        None => return,
+    };
+    if secret {
+        let footer = format!("Jet secret {} de {}", report.id, msg.author.name);
+        let mut recipients = vec![msg.author.id, game_master()?];
+        recipients.dedup();
+        for recipient in recipients {
+            recipient.create_dm_channel(ctx)?.send_message(ctx, |m| {
+                m.embed(|e| {
+                    report.fill(e);
+                    e.footer(|f| f.text(&footer))
+                })
+            })?;
+        }
+        msg.channel_id.send_message(ctx, |m| {
+            m.content(
+                MessageBuilder::new()
+                    .mention(&msg.author)
+                    .push(" a fait un jet secret, identifiant ")
+                    .push_mono(&report.id)
+                    .push("."),
+            )
+        })?;
         return;
+    }
+    let footer = match report.second_chance.clone() {
+        Some(original) => Some(encode(Embedded::EShadowrunSecondChance(
+            ShadowrunSecondChance {
+                roller_raw_id: msg.author.id.0,
+                seed: report.seed,
+                original,
+                rerolled: None,
+            },
+        ))?),
+        None => None,
+    };
+    msg.channel_id.send_message(ctx, |m| {
+        m.embed(|e| {
+            report.fill(e);
+            if let Some(footer) = &footer {
+                e.footer(|f| f.text(footer));
+            }
+            e
+        });
+        if footer.is_some() {
+            m.reactions(vec!["🔁"]);
+        }
+        m
+    })?;
+}
+
+// rolls written as `[[EXPR]]` in any message of the channels that enabled them, in the
+// Shadowrun mode
+pub fn inline(ctx: &Context, msg: &Message) -> AVoid {
+    if msg.author.bot || msg.content.starts_with(crate::PREFIX) {
+        return Ok(());
+    }
+    let parts = inline_rolls(&msg.content);
+    if parts.is_empty() || !inline_enabled(msg.channel_id)? {
+        return Ok(());
+    }
+    let edition = channel_edition(msg.channel_id)?;
+    let mut tests = vec![];
+    // brackets that do not hold a roll are most likely ordinary text, left unanswered
+    for part in parts {
+        if let Ok((expr, test, breakdown)) = user_test(msg.author.id, part, edition)? {
+            tests.push((part, expr, AnyTest::Shadowrun(test), breakdown))
+        }
+    }
+    if tests.is_empty() {
+        return Ok(());
+    }
+    if tests.len() as u64 > MAX_ROLLS {
+        msg.reply(ctx, format!("pas plus de {} jets par message.", MAX_ROLLS))?;
+        return Ok(());
+    }
+    let report = match roll_tests(ctx, msg, None, edition, false, tests, false)? {
+        Some(report) => report,
+        None => return Ok(()),
     };
-    let mut rng = thread_rng();
-    let (summary, details) = resolve(&mut rng, test);
-    msg.channel_id
-        .send_message(ctx, |m| m.embed(|e| e.title(summary).description(details)))?;
+    msg.channel_id.send_message(ctx, |m| {
+        m.content(MessageBuilder::new().mention(&msg.author))
+            .embed(|e| {
+                report.fill(e);
+                e
+            })
+    })?;
+    Ok(())
+}
+
+// errors about secret rolls are sent in private, as they would reveal the roll
+fn reply(ctx: &Context, msg: &Message, secret: bool, content: impl AsRef<str>) -> AVoid {
+    if secret {
+        msg.author
+            .id
+            .create_dm_channel(ctx)?
+            .say(ctx, content.as_ref())?;
+    } else {
+        msg.reply(ctx, content)?;
+    }
+    Ok(())
+}
+
+// what `sr roll` and inline rolls share once their expressions are parsed: spending edge,
+// rolling and recording, or nothing if the roller lacks edge
+fn roll_tests(
+    ctx: &Context,
+    msg: &Message,
+    system: Option<DiceSystem>,
+    edition: Edition,
+    buy: bool,
+    mut tests: Vec<(&str, String, AnyTest, Breakdown)>,
+    secret: bool,
+) -> ARes<Option<Report>> {
+    let wounds = if system.is_none() {
+        wound_modifier(msg.author.id)?
+    } else {
+        0
+    };
+    let setup = RollSetup {
+        seed: thread_rng().gen(),
+        system,
+        edition,
+        wounds,
+        buy,
+    };
+    let mut edge_cost = 0;
+    for (_, _, test, _) in tests.iter_mut() {
+        if let AnyTest::Shadowrun(test) = test {
+            setup.adjust(test);
+            edge_cost += test.edge_cost();
+        }
+    }
+    let edge_left = match spend_edge(ctx, msg.author.id, edge_cost)? {
+        Ok(left) => left,
+        Err(error) => {
+            reply(ctx, msg, secret, error)?;
+            return Ok(None);
+        }
+    };
+    let exprs: Vec<String> = tests.iter().map(|(_, expr, _, _)| expr.clone()).collect();
+    let resolutions = setup.resolve(tests.iter().map(|(_, _, test, _)| test.clone()));
+    let resolutions: Vec<(&str, Resolution)> = tests
+        .iter()
+        .zip(resolutions)
+        .map(|((part, _, _, breakdown), mut res)| {
+            res.details.insert_str(0, &breakdown.render());
+            (*part, res)
+        })
+        .collect();
+    history::record(ctx, msg, &setup, &exprs, &resolutions, secret)?;
+    Ok(Some(Report::new(resolutions, wounds, edge_left, &setup)))
+}
+
+// what `[[` and `]]` enclose, the last brackets of a run closing the roll as in `[[12[4]]]`
+fn inline_rolls(mut input: &str) -> Vec<&str> {
+    let mut rolls = vec![];
+    while let Some(start) = input.find("[[") {
+        input = &input[start + 2..];
+        let end = match input.find("]]") {
+            Some(end) => end + input[end..].find(|c| c != ']').unwrap_or(input.len() - end) - 2,
+            None => break,
+        };
+        if !input[..end].trim().is_empty() {
+            rolls.push(input[..end].trim());
+        }
+        input = &input[end + 2..];
+    }
+    rolls
+}
+
+// a Shadowrun test and its expansion, or the reason it cannot be rolled
+pub fn user_test(
+    user_id: UserId,
+    part: &str,
+    edition: Edition,
+) -> ARes<Result<(String, ShadowrunTest, Breakdown), String>> {
+    let expr = match user_expand(user_id, part)? {
+        Ok(expr) => expr,
+        Err(name) => return Ok(Err(format!("terme inconnu : `{}`.", name))),
+    };
+    let test = match shadowrun_tests(&expr) {
+        Ok((_, test)) => test,
+        Err(err) => return Ok(Err(describe_error(&expr, err))),
+    };
+    Ok(if edition == Edition::Sr6 && test.sr5_edge() {
+        Err(
+            "en 6e édition, la chance se dépense en atouts (`+push`, `+reroll`, `+add`)."
+                .to_owned(),
+        )
+    } else if edition == Edition::Sr5 && test.boosted() {
+        Err("les atouts n’existent qu’en 6e édition.".to_owned())
+    } else {
+        let breakdown = Breakdown::of_expr(&expr);
+        Ok((expr, test, breakdown))
+    })
+}
+
+// besides the expanded expressions, what it takes to roll a message again
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct RollSetup {
+    pub seed: u32,
+    pub system: Option<DiceSystem>,
+    pub edition: Edition,
+    pub wounds: u64,
+    pub buy: bool,
+}
+
+impl RollSetup {
+    pub fn id(&self) -> String {
+        format!("{:08x}", self.seed)
+    }
+
+    fn adjust(&self, test: &mut ShadowrunTest) {
+        if self.buy {
+            test.buy_hits();
+        }
+        test.reduce_pool(self.wounds);
+    }
+
+    // every test of the message draws from the same generator, in order; it is named rather
+    // than `StdRng`, whose algorithm may change with rand and break `sr verify`
+    fn rng(&self) -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(self.seed.into())
+    }
+
+    fn resolve(&self, tests: impl Iterator<Item = AnyTest>) -> Vec<Resolution> {
+        let mut rng = self.rng();
+        tests
+            .map(|test| match test {
+                AnyTest::Shadowrun(test) => resolve(&mut rng, test, self.edition),
+                AnyTest::Dice(test) => dice::resolve(&mut rng, test),
+            })
+            .collect()
+    }
+}
+
+// a bare pool, drawn as `sr verify` replays it from the number of dice
+pub fn setup_pool(edition: Edition, pool: u64) -> (RollSetup, ShadowrunRoll) {
+    let setup = RollSetup {
+        seed: thread_rng().gen(),
+        system: None,
+        edition,
+        wounds: 0,
+        buy: false,
+    };
+    let res = shadowrun_roll(&mut setup.rng(), pool, false, None, edition);
+    (setup, res)
+}
+
+// the rolls of a message from their expanded expressions, as they were first resolved
+pub fn replay(setup: &RollSetup, exprs: &[&str]) -> Option<Vec<Resolution>> {
+    let tests = exprs
+        .iter()
+        .map(|expr| match setup.system {
+            Some(system) => system.parse(expr).map(AnyTest::Dice),
+            None => {
+                let (_, mut test) = shadowrun_tests(expr).ok()?;
+                setup.adjust(&mut test);
+                Some(AnyTest::Shadowrun(test))
+            }
+        })
+        .collect::<Option<Vec<AnyTest>>>()?;
+    Some(setup.resolve(tests.into_iter()))
+}
+
+// the edge left on the active character, if any, or why it cannot be spent
+fn spend_edge(ctx: &Context, user_id: UserId, cost: u64) -> ARes<Result<Option<u64>, String>> {
+    if cost == 0 {
+        return Ok(Ok(None));
+    }
+    Ok(
+        match update_active(ctx, user_id, |sheet| sheet.spend_edge(cost))? {
+            Some(Ok(left)) => Ok(Some(left)),
+            Some(Err(available)) => Err(format!(
+                "pas assez de chance : {} point(s) nécessaire(s), {} disponible(s).",
+                cost, available
+            )),
+            None => Ok(None),
+        },
+    )
+}
+
+// one embed for all the rolls of a message
+struct Report {
+    summary: String,
+    details: String,
+    sections: Vec<(String, String)>,
+    second_chance: Option<SecondChance>,
+    seed: u32,
+    id: String,
+}
+
+impl Report {
+    fn new(
+        mut resolutions: Vec<(&str, Resolution)>,
+        wounds: u64,
+        edge_left: Option<u64>,
+        setup: &RollSetup,
+    ) -> Self {
+        let id = setup.id();
+        let (summary, mut details, sections, second_chance) = if resolutions.len() == 1 {
+            let (_, res) = resolutions.remove(0);
+            (res.summary, res.details, vec![], res.second_chance)
+        } else {
+            let summary = match resolutions
+                .iter()
+                .map(|(_, res)| res.hits)
+                .sum::<Option<u64>>()
+            {
+                Some(hits) => format!("{} jets, {} réussites au total.", resolutions.len(), hits),
+                None => format!("{} jets.", resolutions.len()),
+            };
+            let sections: Vec<(String, String)> = resolutions
+                .into_iter()
+                .enumerate()
+                .map(|(i, (part, res))| {
+                    (
+                        format!("{}. `{}` – {}", i + 1, part, res.summary),
+                        res.details,
+                    )
+                })
+                .collect();
+            (summary, String::new(), sections, None)
+        };
+        if wounds > 0 {
+            let mut breakdown = Breakdown::default();
+            breakdown.push("blessures", -(wounds as i64));
+            details.insert_str(0, &breakdown.render());
+        }
+        if let Some(left) = edge_left {
+            details.push_str(&format!("\nChance restante : {}.", left));
+        }
+        details.push_str(&format!("\nIdentifiant du jet : `{}`.", id));
+        Report {
+            summary,
+            details: details.trim().to_owned(),
+            sections,
+            second_chance,
+            seed: setup.seed,
+            id,
+        }
+    }
+
+    fn fill(&self, e: &mut CreateEmbed) {
+        e.title(&self.summary);
+        if !self.details.is_empty() {
+            e.description(&self.details);
+        }
+        for (name, value) in &self.sections {
+            e.field(name, value, false);
+        }
+    }
+}
+
+#[derive(Clone)]
+enum AnyTest {
+    Shadowrun(ShadowrunTest),
+    Dice(DiceTest),
 }
 
-enum ShadowrunTest {
+#[derive(Clone)]
+pub enum ShadowrunTest {
     Simple(ShadowrunSimple),
     Extended(ShadowrunExtended),
     Opposed(ShadowrunOpposed),
+    Team(ShadowrunTeam),
 }
 
-struct ShadowrunSimple {
-    pool: u64,
-    edge: bool,
-    limit: Option<u64>,
-    threshold: Option<u64>,
+#[derive(Clone)]
+pub struct ShadowrunSimple {
+    pub pool: u64,
+    pub edge: bool,
+    pub limit: Option<u64>,
+    pub threshold: Option<u64>,
+    pub boosts: Vec<Boost>,
+    pub buy: bool,
 }
 
-struct ShadowrunExtended {
-    pool: u64,
-    edge_number: u64,
-    limit: Option<u64>,
-    threshold: u64,
-    interval: Option<Duration>,
+#[derive(Clone, Copy, PartialEq)]
+pub enum Boost {
+    Push,
+    Reroll,
+    Add,
 }
 
-struct ShadowrunOpposed {
-    my_pool: u64,
-    my_edge: bool,
-    my_limit: Option<u64>,
-    their_pool: u64,
-    their_edge: bool,
-    their_limit: Option<u64>,
+#[derive(Clone)]
+pub struct ShadowrunExtended {
+    pub pool: u64,
+    pub edge_number: u64,
+    pub limit: Option<u64>,
+    pub threshold: u64,
+    pub interval: Option<Duration>,
+    pub max_duration: Option<Duration>,
 }
 
 #[derive(Clone)]
-struct ShadowrunRoll {
-    hits: u64,
-    glitch: bool,
-    all: Vec<u8>,
+pub struct ShadowrunOpposed {
+    pub my_pool: u64,
+    pub my_edge: bool,
+    pub my_limit: Option<u64>,
+    pub their_pool: u64,
+    pub their_edge: bool,
+    pub their_limit: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct ShadowrunTeam {
+    pub leader: ShadowrunSimple,
+    pub assistants: Vec<u64>,
+}
+
+impl ShadowrunTest {
+    pub fn reduce_pool(&mut self, by: u64) {
+        let pool = match self {
+            ShadowrunTest::Simple(ShadowrunSimple { pool, .. }) => pool,
+            ShadowrunTest::Extended(ShadowrunExtended { pool, .. }) => pool,
+            ShadowrunTest::Opposed(ShadowrunOpposed { my_pool, .. }) => my_pool,
+            ShadowrunTest::Team(ShadowrunTeam { leader, .. }) => &mut leader.pool,
+        };
+        *pool = pool.saturating_sub(by);
+    }
+
+    pub fn edge_cost(&self) -> u64 {
+        match self {
+            ShadowrunTest::Simple(simple) => simple.edge_cost(),
+            ShadowrunTest::Extended(ShadowrunExtended { edge_number, .. }) => *edge_number,
+            ShadowrunTest::Opposed(ShadowrunOpposed { my_edge, .. }) => *my_edge as u64,
+            ShadowrunTest::Team(ShadowrunTeam { leader, .. }) => leader.edge_cost(),
+        }
+    }
+
+    pub fn sr5_edge(&self) -> bool {
+        match self {
+            ShadowrunTest::Simple(ShadowrunSimple { edge, .. }) => *edge,
+            ShadowrunTest::Extended(ShadowrunExtended { edge_number, .. }) => *edge_number > 0,
+            ShadowrunTest::Opposed(ShadowrunOpposed {
+                my_edge,
+                their_edge,
+                ..
+            }) => *my_edge || *their_edge,
+            ShadowrunTest::Team(ShadowrunTeam { leader, .. }) => leader.edge,
+        }
+    }
+
+    pub fn can_buy(&self) -> bool {
+        matches!(self, ShadowrunTest::Simple(_) | ShadowrunTest::Team(_))
+    }
+
+    pub fn buy_hits(&mut self) {
+        match self {
+            ShadowrunTest::Simple(simple) => simple.buy = true,
+            ShadowrunTest::Team(ShadowrunTeam { leader, .. }) => leader.buy = true,
+            _ => {}
+        }
+    }
+
+    pub fn boosted(&self) -> bool {
+        match self {
+            ShadowrunTest::Simple(simple) => !simple.boosts.is_empty(),
+            ShadowrunTest::Team(ShadowrunTeam { leader, .. }) => !leader.boosts.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+impl ShadowrunExtended {
+    pub fn max_intervals(&self) -> Option<u64> {
+        match (self.interval, self.max_duration) {
+            (Some(interval), Some(max)) => {
+                Some((max.num_seconds() / interval.num_seconds()) as u64)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ShadowrunSimple {
+    fn edge_cost(&self) -> u64 {
+        if self.buy {
+            return 0;
+        }
+        self.edge as u64 + self.boosts.iter().map(|boost| boost.cost()).sum::<u64>()
+    }
+}
+
+impl Boost {
+    fn cost(self) -> u64 {
+        match self {
+            Boost::Push => 4,
+            Boost::Reroll => 1,
+            Boost::Add => 2,
+        }
+    }
+}
+
+pub fn wound_modifier(user_id: UserId) -> ARes<u64> {
+    Ok(active_character(user_id)?
+        .map(|character| character.wound_modifier())
+        .unwrap_or(0))
+}
+
+#[derive(Clone)]
+pub struct ShadowrunRoll {
+    pub hits: u64,
+    pub glitch: bool,
+    pub all: Vec<u8>,
+}
+
+pub struct Resolution {
+    pub summary: String,
+    pub hits: Option<u64>,
+    pub details: String,
+    pub second_chance: Option<SecondChance>,
+    // the dice of the roller, for the history
+    pub rolls: Vec<ShadowrunRoll>,
 }
 
 impl ShadowrunRoll {
-    fn fmt_details(&self) -> String {
+    pub fn evaluate(all: Vec<u8>, limit: Option<u64>, edition: Edition) -> ShadowrunRoll {
+        let pool = all.len() as u64;
+        let mut hits = all.iter().filter(|&&single| single >= 5).count() as u64;
+        let ones = all.iter().filter(|&&single| single == 1).count() as u64;
+        if let Some(limit) = limit {
+            hits = min(hits, limit);
+        }
+        let glitch = edition.glitch(ones, pool);
+        ShadowrunRoll { hits, glitch, all }
+    }
+
+    pub fn fmt_details(&self) -> String {
         let mut mb = MessageBuilder::new();
         let mut all_peek = self.all.iter().peekable();
         while let Some(single) = all_peek.next() {
@@ -130,7 +781,7 @@ impl ShadowrunRoll {
         mb.0
     }
 
-    fn fumble(&self) -> bool {
+    pub fn fumble(&self) -> bool {
         self.hits == 0 && self.glitch
     }
 }
@@ -140,58 +791,136 @@ enum ExtendedOutcome {
     Fumble,
     GlitchedBelowOne,
     PoolExhausted,
+    OutOfTime,
 }
 
-fn resolve(rng: &mut impl Rng, test: ShadowrunTest) -> (String, String) {
+pub fn simple_summary(res: &ShadowrunRoll, threshold: Option<u64>) -> String {
+    if res.fumble() {
+        "Échec critique !".to_owned()
+    } else {
+        let glitch = if res.glitch {
+            " – complication !"
+        } else {
+            "."
+        };
+        if let Some(threshold) = threshold {
+            match res.hits.cmp(&threshold) {
+                Ordering::Less => format!("Échec{}", glitch),
+                Ordering::Equal => format!("Réussite de justesse ou frôlé{}", glitch),
+                Ordering::Greater => format!(
+                    "Réussite avec {} succès excédentaires{}",
+                    res.hits - threshold,
+                    glitch
+                ),
+            }
+        } else {
+            format!(
+                "{} réussite{}{}",
+                res.hits,
+                if res.hits > 1 { "s" } else { "" },
+                glitch
+            )
+        }
+    }
+}
+
+fn resolve(rng: &mut impl Rng, test: ShadowrunTest, edition: Edition) -> Resolution {
     match test {
+        ShadowrunTest::Simple(ShadowrunSimple {
+            pool,
+            limit,
+            threshold,
+            buy: true,
+            ..
+        }) => {
+            let bought = pool / 4;
+            let hits = edition
+                .limit(limit)
+                .map_or(bought, |limit| min(bought, limit));
+            let res = ShadowrunRoll {
+                hits,
+                glitch: false,
+                all: vec![],
+            };
+            Resolution {
+                summary: simple_summary(&res, threshold),
+                hits: Some(hits),
+                details: format!("Réussites achetées avec une réserve de {}.", pool),
+                second_chance: None,
+                rolls: vec![],
+            }
+        }
         ShadowrunTest::Simple(ShadowrunSimple {
             pool,
             edge,
             limit,
             threshold,
+            boosts,
+            ..
         }) => {
-            let res = shadowrun_roll(rng, pool, edge, limit);
-            let summary = if res.fumble() {
-                "Échec critique !".to_owned()
-            } else {
-                let glitch = if res.glitch {
-                    " – complication !"
-                } else {
-                    "."
-                };
-                if let Some(threshold) = threshold {
-                    match res.hits.cmp(&threshold) {
-                        Ordering::Less => format!("Échec{}", glitch),
-                        Ordering::Equal => format!("Réussite de justesse ou frôlé{}", glitch),
-                        Ordering::Greater => format!(
-                            "Réussite avec {} succès excédentaires{}",
-                            res.hits - threshold,
-                            glitch
-                        ),
+            let explode = edge || boosts.contains(&Boost::Push);
+            let mut res = shadowrun_roll(rng, pool, explode, limit, edition);
+            let mut boosted = vec![];
+            for boost in boosts {
+                let failed = res
+                    .all
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &single)| single < 5)
+                    .map(|(i, &single)| (single, i));
+                match (boost, failed.clone().min(), failed.max()) {
+                    (Boost::Reroll, Some((single, i)), _) => {
+                        res.all[i] = Uniform::new_inclusive(1, 6).sample(rng);
+                        boosted.push(format!("relance d’un {} en {}", single, res.all[i]));
                     }
-                } else {
-                    format!(
-                        "{} réussite{}{}",
-                        res.hits,
-                        if res.hits > 1 { "s" } else { "" },
-                        glitch
-                    )
+                    (Boost::Add, _, Some((single, i))) => {
+                        res.all[i] += 1;
+                        boosted.push(format!("+1 sur un {}", single));
+                    }
+                    (Boost::Push, _, _) => {}
+                    _ => boosted.push("atout sans effet".to_owned()),
                 }
-            };
+            }
+            if !boosted.is_empty() {
+                res = ShadowrunRoll::evaluate(res.all, None, edition);
+            }
+            let summary = simple_summary(&res, threshold);
             let mut details = format!(
                 "Détail du jet {}: ",
-                if edge { "(avec chance) " } else { "" }
+                if explode { "(avec chance) " } else { "" }
             );
             details.push_str(&res.fmt_details());
-            (summary, details)
+            if !boosted.is_empty() {
+                details.push_str(&format!("\nAtouts : {}.", boosted.join(", ")));
+            }
+            // chance cannot be used twice on a test, nor to cancel a critical glitch
+            let second_chance = if edge || res.fumble() || edition == Edition::Sr6 {
+                None
+            } else {
+                Some(SecondChance {
+                    limit,
+                    threshold,
+                    dice: res.all.clone(),
+                })
+            };
+            Resolution {
+                summary,
+                hits: Some(res.hits),
+                details,
+                second_chance,
+                rolls: vec![res],
+            }
         }
-        ShadowrunTest::Extended(ShadowrunExtended {
-            mut pool,
-            mut edge_number,
-            limit,
-            threshold,
-            interval,
-        }) => {
+        ShadowrunTest::Extended(extended) => {
+            let max_intervals = extended.max_intervals();
+            let ShadowrunExtended {
+                mut pool,
+                mut edge_number,
+                limit,
+                threshold,
+                interval,
+                ..
+            } = extended;
             let mut all_res = vec![];
             let mut hits: u64 = 0;
             let mut penalties = vec![];
@@ -199,7 +928,10 @@ fn resolve(rng: &mut impl Rng, test: ShadowrunTest) -> (String, String) {
                 if pool == 0 {
                     break ExtendedOutcome::PoolExhausted;
                 }
-                let partial = shadowrun_roll(rng, pool, edge_number > 0, limit);
+                if max_intervals == Some(all_res.len() as u64) {
+                    break ExtendedOutcome::OutOfTime;
+                }
+                let partial = shadowrun_roll(rng, pool, edge_number > 0, limit, edition);
                 all_res.push(partial.clone());
                 if partial.fumble() {
                     break ExtendedOutcome::Fumble;
@@ -219,18 +951,24 @@ fn resolve(rng: &mut impl Rng, test: ShadowrunTest) -> (String, String) {
                 pool = pool.saturating_sub(1);
                 edge_number = edge_number.saturating_sub(1);
             };
+            let elapsed = |intervals: usize| match interval {
+                Some(interval) => format!(" ({})", fmt_duration(interval * intervals as i32)),
+                None => String::new(),
+            };
             let mut details = String::new();
             let mut nr = 0;
+            let mut penalties = penalties.into_iter();
             for (i, res) in all_res.iter().enumerate() {
                 nr += res.hits;
                 details.push_str(&format!(
-                    "Lancer {} : {}{}\n",
+                    "Lancer {}{} : {}{}\n",
                     i + 1,
+                    elapsed(i + 1),
                     res.fmt_details(),
                     if res.fumble() {
                         " | Échec critique ! Le test s’arrête ici...".to_owned()
                     } else if res.glitch {
-                        let pen = penalties.pop().unwrap();
+                        let pen = penalties.next().unwrap();
                         nr = nr.saturating_sub(pen);
                         format!(
                             " | Complication ! Pénalité de {} réussites, plus que {}.",
@@ -242,67 +980,302 @@ fn resolve(rng: &mut impl Rng, test: ShadowrunTest) -> (String, String) {
                 ))
             }
             let summary = match result {
-                ExtendedOutcome::Success => {
-                    format!("Réussite au bout de {} intervalles", all_res.len())
-                }
+                ExtendedOutcome::Success => format!(
+                    "Réussite au bout de {} intervalles{}",
+                    all_res.len(),
+                    elapsed(all_res.len())
+                ),
                 ExtendedOutcome::Fumble => "Échec critique !".to_string(),
                 ExtendedOutcome::GlitchedBelowOne => "Échec à force de complications !".to_string(),
                 ExtendedOutcome::PoolExhausted => "Échec faute de réserve !".to_string(),
+                ExtendedOutcome::OutOfTime => {
+                    format!("Échec faute de temps{} !", elapsed(all_res.len()))
+                }
             };
-            (summary, details)
+            Resolution {
+                summary,
+                hits: Some(hits),
+                details,
+                second_chance: None,
+                rolls: all_res,
+            }
+        }
+        ShadowrunTest::Opposed(ShadowrunOpposed {
+            my_pool,
+            my_edge,
+            my_limit,
+            their_pool,
+            their_edge,
+            their_limit,
+        }) => {
+            let mine = shadowrun_roll(rng, my_pool, my_edge, my_limit, edition);
+            let theirs = shadowrun_roll(rng, their_pool, their_edge, their_limit, edition);
+            let mut summary = if mine.fumble() {
+                "Échec critique".to_owned()
+            } else {
+                match mine.hits.cmp(&theirs.hits) {
+                    Ordering::Greater => {
+                        format!("Réussite avec {} succès nets", mine.hits - theirs.hits)
+                    }
+                    Ordering::Equal => "Égalité, l’opposant l’emporte".to_owned(),
+                    Ordering::Less => format!(
+                        "Échec, l’opposant a {} succès nets",
+                        theirs.hits - mine.hits
+                    ),
+                }
+            };
+            if mine.glitch && !mine.fumble() {
+                summary.push_str(" – complication");
+            }
+            if theirs.fumble() {
+                summary.push_str(" – échec critique adverse");
+            } else if theirs.glitch {
+                summary.push_str(" – complication adverse");
+            }
+            summary.push_str(if mine.glitch || theirs.glitch {
+                " !"
+            } else {
+                "."
+            });
+            let details = format!(
+                "Jet actif {}: {}\nJet opposé {}: {}\nRéussites : {} contre {}.",
+                if my_edge { "(avec chance) " } else { "" },
+                mine.fmt_details(),
+                if their_edge { "(avec chance) " } else { "" },
+                theirs.fmt_details(),
+                mine.hits,
+                theirs.hits
+            );
+            Resolution {
+                summary,
+                hits: Some(mine.hits),
+                details,
+                second_chance: None,
+                rolls: vec![mine],
+            }
+        }
+        ShadowrunTest::Team(ShadowrunTeam { leader, assistants }) => {
+            let mut details = String::new();
+            let mut bonus = 0;
+            for (i, &pool) in assistants.iter().enumerate() {
+                let res = shadowrun_roll(rng, pool, false, None, edition);
+                bonus += res.hits;
+                details.push_str(&format!(
+                    "Assistant {} : {} | {}\n",
+                    i + 1,
+                    res.fmt_details(),
+                    simple_summary(&res, None)
+                ));
+            }
+            details.push_str(&format!(
+                "Bonus d’équipe : +{} à la réserve{}.\n",
+                bonus,
+                if edition == Edition::Sr5 {
+                    " et à la limite"
+                } else {
+                    ""
+                }
+            ));
+            let leader = ShadowrunSimple {
+                pool: leader.pool.saturating_add(bonus),
+                limit: leader.limit.map(|limit| limit.saturating_add(bonus)),
+                ..leader
+            };
+            let res = resolve(rng, ShadowrunTest::Simple(leader), edition);
+            details.push_str(&res.details);
+            Resolution { details, ..res }
         }
-        ShadowrunTest::Opposed(_) => unimplemented!(),
     }
 }
 
-fn shadowrun_roll(
+// there are no limits in 6th edition
+pub fn shadowrun_roll(
     rng: &mut impl Rng,
-    mut pool: u64,
+    pool: u64,
     edge: bool,
-    mut limit: Option<u64>,
+    limit: Option<u64>,
+    edition: Edition,
 ) -> ShadowrunRoll {
-    let mut hits = 0;
     let mut unrolled = pool;
-    let mut ones = 0;
     let mut all = vec![];
-    if edge {
-        limit = None;
-    }
     while unrolled > 0 {
         unrolled -= 1;
         let single = Uniform::new_inclusive(1, 6).sample(rng);
         all.push(single);
-        if single >= 5 {
-            hits += 1;
-        }
-        if single == 1 {
-            ones += 1;
-        }
         if edge && single == 6 {
-            pool += 1;
             unrolled += 1;
         }
     }
-    if let Some(limit) = limit {
-        hits = min(hits, limit);
+    let limit = if edge { None } else { edition.limit(limit) };
+    ShadowrunRoll::evaluate(all, limit, edition)
+}
+
+// in the largest unit that divides it exactly
+fn fmt_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds();
+    let (size, singular, plural) = DURATION_UNITS
+        .iter()
+        .find(|(size, _, _)| seconds >= *size && seconds % size == 0)
+        .cloned()
+        .unwrap_or((1, "seconde", "secondes"));
+    let count = seconds / size;
+    format!("{} {}", count, if count > 1 { plural } else { singular })
+}
+
+// expressions separated by `;`, each optionally followed by a repeat count such as ` x3`
+fn split_rolls(input: &str) -> Option<Vec<(&str, u64)>> {
+    let mut rolls = vec![];
+    let mut total: u64 = 0;
+    for part in input.split(';') {
+        let part = part.trim();
+        let (expr, count) = match part.rfind('x') {
+            Some(i) if part[..i].ends_with(char::is_whitespace) => {
+                (part[..i].trim_end(), part[i + 1..].parse().ok()?)
+            }
+            _ => (part, 1),
+        };
+        if expr.is_empty() || count == 0 || count > MAX_ROLLS {
+            return None;
+        }
+        total = total
+            .checked_add(count)
+            .filter(|&total| total <= MAX_ROLLS)?;
+        rolls.push((expr, count));
     }
-    let glitch = ones >= pool / 2 + pool % 2;
-    ShadowrunRoll { hits, glitch, all }
+    Some(rolls)
+}
+
+// the furthest failure across alternatives, with what could have been read there
+pub struct ExprError<'a> {
+    remaining: &'a str,
+    expected: Vec<String>,
+    context: Option<&'static str>,
 }
 
-fn shadowrun_tests(input: &str) -> IResult<&str, ShadowrunTest> {
+type ExprResult<'a, T> = IResult<&'a str, T, ExprError<'a>>;
+
+impl<'a> ParseError<&'a str> for ExprError<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        let expected = match kind {
+            ErrorKind::Digit | ErrorKind::MapRes => Some("un nombre"),
+            ErrorKind::Eof => Some("la fin de l’expression"),
+            ErrorKind::Tag => Some("un mot-clé"),
+            ErrorKind::Space => Some("une espace"),
+            _ => None,
+        };
+        ExprError {
+            remaining: input,
+            expected: expected.into_iter().map(str::to_owned).collect(),
+            context: None,
+        }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: &'a str, c: char) -> Self {
+        ExprError {
+            remaining: input,
+            expected: vec![format!("`{}`", c)],
+            context: None,
+        }
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.remaining.len().cmp(&other.remaining.len()) {
+            Ordering::Less => self,
+            Ordering::Greater => other,
+            Ordering::Equal => {
+                for expected in other.expected {
+                    if !self.expected.contains(&expected) {
+                        self.expected.push(expected);
+                    }
+                }
+                self.context = self.context.or(other.context);
+                self
+            }
+        }
+    }
+
+    fn add_context(_: &'a str, context: &'static str, mut other: Self) -> Self {
+        other.context = other.context.or(Some(context));
+        other
+    }
+}
+
+pub fn describe_error(expr: &str, err: nom::Err<ExprError<'_>>) -> String {
+    let err = match err {
+        nom::Err::Error(err) | nom::Err::Failure(err) => err,
+        nom::Err::Incomplete(_) => return "expression incomplète.".to_owned(),
+    };
+    let column = expr[..expr.len() - err.remaining.len()].chars().count() + 1;
+    let mut description = if err.expected.is_empty() {
+        format!("expression incorrecte à la colonne {}", column)
+    } else {
+        format!(
+            "attendu à la colonne {} : {}",
+            column,
+            err.expected.join(" ou ")
+        )
+    };
+    if let Some(context) = err.context {
+        description.push_str(&format!(", {}", context));
+    }
+    format!(
+        "{}.\n```\n{}\n{}^\n```",
+        description,
+        expr,
+        " ".repeat(column - 1)
+    )
+}
+
+pub fn pool_value(input: &str) -> Option<u64> {
+    all_consuming(series)(input).ok().map(|(_, pool)| pool)
+}
+
+pub fn shadowrun_tests(input: &str) -> ExprResult<'_, ShadowrunTest> {
     alt((
-        map(opposed_test, ShadowrunTest::Opposed),
-        map(extended_test, ShadowrunTest::Extended),
-        map(simple_test, ShadowrunTest::Simple),
+        map(all_consuming(opposed_test), ShadowrunTest::Opposed),
+        map(all_consuming(extended_test), ShadowrunTest::Extended),
+        map(all_consuming(team_test), ShadowrunTest::Team),
+        map(all_consuming(simple_test), ShadowrunTest::Simple),
     ))(input)
 }
 
-fn opposed_test(input: &str) -> IResult<&str, ShadowrunOpposed> {
-    map(tag("lolilol"), |i| unimplemented!())(input)
+fn opposed_test(input: &str) -> ExprResult<'_, ShadowrunOpposed> {
+    let (input, (my_pool, my_edge, my_limit)) = opposed_side(input)?;
+    let (input, _) = delimited(space0, tag(VERSUS), space0)(input)?;
+    let (input, (their_pool, their_edge, their_limit)) = opposed_side(input)?;
+    Ok((
+        input,
+        ShadowrunOpposed {
+            my_pool,
+            my_edge,
+            my_limit,
+            their_pool,
+            their_edge,
+            their_limit,
+        },
+    ))
+}
+
+fn opposed_side(input: &str) -> ExprResult<'_, (u64, bool, Option<u64>)> {
+    let (input, pool) = series(input)?;
+    let (input, edge) = opt(char('*'))(input)?;
+    let (input, limit) = opt(limit)(input)?;
+    Ok((input, (pool, edge.is_some(), limit)))
 }
 
-fn intervals(input: &str) -> IResult<&str, Duration> {
+// once opened, a limit is the same in every form of test
+fn limit(input: &str) -> ExprResult<'_, u64> {
+    preceded(
+        char('['),
+        cut(terminated(series, context("après la limite", char(']')))),
+    )(input)
+}
+
+fn intervals(input: &str) -> ExprResult<'_, Duration> {
     let (input, vstr) = alt((
         tag("1tc"),
         tag("1m"),
@@ -312,7 +1285,13 @@ fn intervals(input: &str) -> IResult<&str, Duration> {
         tag("1j"),
         tag("1S"),
         tag("1M"),
-    ))(input)?;
+    ))(input)
+    .map_err(|err| {
+        err.map(|err| ExprError {
+            expected: vec!["un intervalle".to_owned()],
+            ..err
+        })
+    })?;
     Ok((
         input,
         match vstr {
@@ -329,14 +1308,22 @@ fn intervals(input: &str) -> IResult<&str, Duration> {
     ))
 }
 
-fn extended_test(input: &str) -> IResult<&str, ShadowrunExtended> {
+fn extended_test(input: &str) -> ExprResult<'_, ShadowrunExtended> {
     let (input, pool) = series(input)?;
     let (input, edge) = opt(char('*'))(input)?;
     let (input, edge_count) = opt(series)(input)?;
-    let (input, limit) = opt(delimited(char('['), series, char(']')))(input)?;
+    let (input, limit) = opt(limit)(input)?;
     let (input, threshold) = delimited(char('('), series, char(','))(input)?;
-    let (input, interval) = opt(intervals)(input)?;
-    let (input, _) = char(')')(input)?;
+    let (input, (interval, max_duration)) = alt((
+        map(char(')'), |_| (None, None)),
+        map(
+            cut(terminated(
+                interval_and_duration,
+                context("après l’intervalle", char(')')),
+            )),
+            |(interval, max_duration)| (Some(interval), max_duration),
+        ),
+    ))(input)?;
     Ok((
         input,
         ShadowrunExtended {
@@ -345,35 +1332,144 @@ fn extended_test(input: &str) -> IResult<&str, ShadowrunExtended> {
             limit,
             threshold,
             interval,
+            max_duration,
         },
     ))
 }
 
-fn simple_test(input: &str) -> IResult<&str, ShadowrunSimple> {
+fn interval_and_duration(input: &str) -> ExprResult<'_, (Duration, Option<Duration>)> {
+    let (input, interval) = intervals(input)?;
+    let (input, max_duration) = opt(preceded(
+        char(','),
+        cut(|input| max_duration(input, interval)),
+    ))(input)?;
+    Ok((input, (interval, max_duration)))
+}
+
+// a maximum shorter than the interval would not leave time for a single roll
+fn max_duration(input: &str, interval: Duration) -> ExprResult<'_, Duration> {
+    let (rest, max) = duration(input)?;
+    if max < interval {
+        return Err(nom::Err::Error(ExprError {
+            remaining: input,
+            expected: vec!["une durée d’au moins un intervalle".to_owned()],
+            context: None,
+        }));
+    }
+    Ok((rest, max))
+}
+
+fn duration(input: &str) -> ExprResult<'_, Duration> {
+    let (input, count) = map_res(digit1, parse_int)(input)?;
+    let (input, unit) = alt((tag("tc"), tag("m"), tag("h"), tag("j"), tag("S"), tag("M")))(input)
+        .map_err(|err| {
+        err.map(|err| ExprError {
+            expected: vec!["une unité de durée".to_owned()],
+            ..err
+        })
+    })?;
+    let unit = match unit {
+        "tc" => Duration::seconds(3),
+        "m" => Duration::minutes(1),
+        "h" => Duration::hours(1),
+        "j" => Duration::days(1),
+        "S" => Duration::weeks(1),
+        "M" => Duration::days(30),
+        _ => unreachable!("previously matched"),
+    };
+    Ok((input, unit * min(count, i32::MAX as u64) as i32))
+}
+
+fn simple_test(input: &str) -> ExprResult<'_, ShadowrunSimple> {
     let (input, pool) = series(input)?;
-    let (input, edge) = opt(char('*'))(input)?;
-    let (input, limit) = opt(delimited(char('['), series, char(']')))(input)?;
-    let (input, threshold) = opt(delimited(char('('), series, char(')')))(input)?;
+    let (input, mark) = opt(one_of("*!"))(input)?;
+    let (input, limit) = opt(limit)(input)?;
+    let (input, threshold) = opt(preceded(char('('), terminated(series, threshold_end)))(input)?;
+    let (input, boosts) = many0(preceded(pair(space0, char('+')), boost))(input)?;
     Ok((
         input,
         ShadowrunSimple {
             pool,
-            edge: edge.is_some(),
+            edge: mark == Some('*'),
             limit,
             threshold,
+            boosts,
+            buy: mark == Some('!'),
         },
     ))
 }
 
-fn series(input: &str) -> IResult<&str, u64> {
-    let (input, head) = map_res(digit1, parse_int)(input)?;
-    fold_many0(
-        tuple((alt((char('+'), char('-'))), map_res(digit1, parse_int))),
-        head,
-        series_sum,
+// a comma after the threshold is left to the extended test, so either may be expected there
+fn threshold_end(input: &str) -> ExprResult<'_, char> {
+    if input.starts_with(',') {
+        Err(nom::Err::Error(ExprError::from_char(input, ')')))
+    } else {
+        cut(context("après le seuil", char(')')))(input).map_err(|err| {
+            err.map(|mut err: ExprError| {
+                err.expected.push("`,`".to_owned());
+                err
+            })
+        })
+    }
+}
+
+fn boost(input: &str) -> ExprResult<'_, Boost> {
+    alt((
+        map(tag(PUSH), |_| Boost::Push),
+        map(tag(REROLL), |_| Boost::Reroll),
+        map(tag(ADD), |_| Boost::Add),
+    ))(input)
+}
+
+fn team_test(input: &str) -> ExprResult<'_, ShadowrunTeam> {
+    let (input, leader) = simple_test(input)?;
+    let (input, _) = tuple((space0, char('+'), tag(TEAM), space1))(input)?;
+    let (input, assistants) = cut(separated_nonempty_list(
+        delimited(space0, char(','), space0),
+        series,
+    ))(input)?;
+    Ok((input, ShadowrunTeam { leader, assistants }))
+}
+
+fn series(input: &str) -> ExprResult<'_, u64> {
+    let (input, head) = term(input)?;
+    fold_many0(tuple((alt((char('+'), char('-'))), term)), head, series_sum)(input)
+}
+
+// a term may be labelled for the breakdown, as in `2{smartlink}`
+fn term(input: &str) -> ExprResult<'_, u64> {
+    terminated(map_res(digit1, parse_int), opt(label))(input)
+}
+
+pub fn labelled_term(input: &str) -> ExprResult<'_, (i64, &str)> {
+    let (input, (sign, value, label)) =
+        tuple((opt(one_of("+-")), map_res(digit1, parse_int), label))(input)?;
+    let value = min(value, i64::MAX as u64) as i64;
+    Ok((
+        input,
+        (if sign == Some('-') { -value } else { value }, label),
+    ))
+}
+
+fn label(input: &str) -> ExprResult<'_, &str> {
+    preceded(
+        char('{'),
+        cut(terminated(
+            label_text,
+            context("après l’étiquette", char('}')),
+        )),
     )(input)
 }
 
+fn label_text(input: &str) -> ExprResult<'_, &str> {
+    is_not("{}")(input).map_err(|err| {
+        err.map(|err| ExprError {
+            expected: vec!["une étiquette".to_owned()],
+            ..err
+        })
+    })
+}
+
 fn series_sum(acc: u64, item: (char, u64)) -> u64 {
     match item.0 {
         '+' => acc.saturating_add(item.1),
@@ -385,3 +1481,276 @@ fn series_sum(acc: u64, item: (char, u64)) -> u64 {
 fn parse_int(input: &str) -> Result<u64, ParseIntError> {
     Ok(u64::from_str(input)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    // draws the given faces in order, whatever the integer type a die is sampled as
+    struct Faces<'a>(std::slice::Iter<'a, u8>);
+
+    impl Faces<'_> {
+        fn face(&mut self) -> u8 {
+            self.0.next().expect("no face left") - 1
+        }
+    }
+
+    impl RngCore for Faces<'_> {
+        fn next_u32(&mut self) -> u32 {
+            ((u64::from(self.face()) << 32) / 6 + 1) as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            ((u128::from(self.face()) << 64) / 6 + 1) as u64
+        }
+
+        fn fill_bytes(&mut self, _: &mut [u8]) {
+            unimplemented!()
+        }
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), rand::Error> {
+            unimplemented!()
+        }
+    }
+
+    fn roll(input: &str, faces: &[u8], edition: Edition) -> Resolution {
+        let mut rng = Faces(faces.iter());
+        let res = resolve(&mut rng, parse(input), edition);
+        assert!(rng.0.next().is_none(), "every face should be drawn");
+        res
+    }
+
+    fn dice(res: &Resolution) -> Vec<Vec<u8>> {
+        res.rolls.iter().map(|roll| roll.all.clone()).collect()
+    }
+
+    fn parse(input: &str) -> ShadowrunTest {
+        match shadowrun_tests(input) {
+            Ok(("", test)) => test,
+            _ => panic!("`{}` should parse", input),
+        }
+    }
+
+    fn simple(input: &str) -> ShadowrunSimple {
+        match parse(input) {
+            ShadowrunTest::Simple(simple) => simple,
+            _ => panic!("`{}` should be a simple test", input),
+        }
+    }
+
+    fn opposed(input: &str) -> ShadowrunOpposed {
+        match parse(input) {
+            ShadowrunTest::Opposed(opposed) => opposed,
+            _ => panic!("`{}` should be an opposed test", input),
+        }
+    }
+
+    #[test]
+    fn subtraction_is_not_opposition() {
+        assert_eq!(simple("12-2").pool, 10);
+        assert_eq!(simple("5+6-2").pool, 9);
+        assert_eq!(simple("5+6-2[4](2)").limit, Some(4));
+    }
+
+    #[test]
+    fn opposed_sides() {
+        let test = opposed("12-2*[5] vs 8+1[3]");
+        assert_eq!(test.my_pool, 10);
+        assert!(test.my_edge);
+        assert_eq!(test.my_limit, Some(5));
+        assert_eq!(test.their_pool, 9);
+        assert!(!test.their_edge);
+        assert_eq!(test.their_limit, Some(3));
+        assert_eq!(opposed("12vs8*").their_pool, 8);
+    }
+
+    #[test]
+    fn labelled_modifiers() {
+        let test = simple("8+2{smartlink}-3{couvert}");
+        assert_eq!(test.pool, 7);
+        assert_eq!(test.threshold, None);
+        assert_eq!(
+            Breakdown::of_expr("8+2{smartlink}-3{couvert}").render(),
+            "Modificateurs : smartlink +2, couvert -3.\n"
+        );
+        assert_eq!(opposed("8-3{couvert} vs 6+2{fatigue}").their_pool, 8);
+        assert!(shadowrun_tests("8+2{}").is_err());
+    }
+
+    #[test]
+    fn maximum_durations() {
+        match parse("10(5,1h,2h)") {
+            ShadowrunTest::Extended(test) => assert_eq!(test.max_intervals(), Some(2)),
+            _ => panic!("should be an extended test"),
+        }
+        match shadowrun_tests("10(5,1h,30m)") {
+            Err(err) => assert!(describe_error("10(5,1h,30m)", err)
+                .starts_with("attendu à la colonne 9 : une durée d’au moins un intervalle")),
+            Ok(_) => panic!("a maximum shorter than the interval should be rejected"),
+        }
+        assert_eq!(fmt_duration(Duration::zero()), "0 seconde");
+        assert_eq!(fmt_duration(Duration::minutes(90)), "90 minutes");
+        assert_eq!(fmt_duration(Duration::days(60)), "2 mois");
+    }
+
+    #[test]
+    fn repeated_rolls() {
+        assert_eq!(
+            split_rolls("8[4] x3; 10(2)"),
+            Some(vec![("8[4]", 3), ("10(2)", 1)])
+        );
+        assert_eq!(split_rolls("8 x20"), Some(vec![("8", 20)]));
+        assert_eq!(split_rolls("8 x21"), None);
+        assert_eq!(split_rolls("8 x18446744073709551615;8"), None);
+        assert_eq!(split_rolls("8 x10;8 x10;8"), None);
+        assert_eq!(split_rolls("8 x0"), None);
+    }
+
+    #[test]
+    fn simple_tests() {
+        let res = roll("4[1](1)", &[6, 5, 1, 2], Edition::Sr5);
+        assert_eq!(res.hits, Some(1));
+        assert_eq!(res.summary, "Réussite de justesse ou frôlé.");
+        assert!(res.second_chance.is_some());
+        let res = roll("3", &[1, 1, 3], Edition::Sr5);
+        assert_eq!(res.summary, "Échec critique !");
+        assert!(res.second_chance.is_none());
+        let res = roll("4(1)", &[1, 1, 5, 5], Edition::Sr5);
+        assert_eq!(
+            res.summary,
+            "Réussite avec 1 succès excédentaires – complication !"
+        );
+        let res = roll("4(1)", &[1, 1, 5, 5], Edition::Sr6);
+        assert_eq!(res.summary, "Réussite avec 1 succès excédentaires.");
+    }
+
+    #[test]
+    fn edge_explodes_and_ignores_the_limit() {
+        let res = roll("2*[1]", &[6, 3, 5], Edition::Sr5);
+        assert_eq!(dice(&res), vec![vec![6, 3, 5]]);
+        assert_eq!(res.hits, Some(2));
+        assert!(res.second_chance.is_none());
+    }
+
+    #[test]
+    fn bought_hits() {
+        let res = roll("13![2](2)", &[], Edition::Sr5);
+        assert_eq!(res.hits, Some(2));
+        assert_eq!(res.summary, "Réussite de justesse ou frôlé.");
+        assert!(res.rolls.is_empty());
+        assert_eq!(roll("13![2]", &[], Edition::Sr6).hits, Some(3));
+    }
+
+    #[test]
+    fn boosts() {
+        let res = roll("3 +reroll +add", &[2, 4, 5, 3], Edition::Sr6);
+        assert_eq!(dice(&res), vec![vec![3, 5, 5]]);
+        assert_eq!(res.summary, "2 réussites.");
+        assert!(res
+            .details
+            .ends_with("Atouts : relance d’un 2 en 3, +1 sur un 4."));
+        let res = roll("1 +add", &[5], Edition::Sr6);
+        assert!(res.details.ends_with("Atouts : atout sans effet."));
+    }
+
+    #[test]
+    fn extended_success() {
+        let res = roll("3(2,1h)", &[5, 2, 3, 6, 4], Edition::Sr5);
+        assert_eq!(res.summary, "Réussite au bout de 2 intervalles (2 heures)");
+        assert_eq!(res.hits, Some(2));
+    }
+
+    #[test]
+    fn extended_penalties() {
+        let res = roll("4(10,)", &[5, 5, 6, 2, 1, 1, 5, 2, 1, 2], Edition::Sr5);
+        assert_eq!(
+            dice(&res),
+            vec![vec![5, 5, 6, 2], vec![1, 1, 5], vec![1, 2]]
+        );
+        assert_eq!(res.summary, "Échec critique !");
+        assert_eq!(res.hits, Some(2));
+        assert!(res
+            .details
+            .contains("Complication ! Pénalité de 2 réussites, plus que 2."));
+        let res = roll("3(5,)", &[5, 2, 3, 1, 5, 3], Edition::Sr5);
+        assert_eq!(res.summary, "Échec à force de complications !");
+    }
+
+    #[test]
+    fn extended_failures() {
+        let res = roll("2(5,)", &[2, 3, 4], Edition::Sr5);
+        assert_eq!(res.summary, "Échec faute de réserve !");
+        let res = roll("5(9,1h,2h)", &[5, 2, 3, 4, 2, 2, 3, 4, 2], Edition::Sr5);
+        assert_eq!(res.summary, "Échec faute de temps (2 heures) !");
+    }
+
+    #[test]
+    fn opposed_resolution() {
+        let res = roll("3 vs 2", &[5, 5, 1, 5, 2], Edition::Sr5);
+        assert_eq!(res.summary, "Réussite avec 1 succès nets.");
+        assert_eq!(dice(&res), vec![vec![5, 5, 1]]);
+        let res = roll("2 vs 2", &[5, 2, 1, 1], Edition::Sr5);
+        assert_eq!(
+            res.summary,
+            "Réussite avec 1 succès nets – échec critique adverse !"
+        );
+        let res = roll("2 vs 2", &[5, 1, 6, 2], Edition::Sr5);
+        assert_eq!(
+            res.summary,
+            "Égalité, l’opposant l’emporte – complication !"
+        );
+    }
+
+    #[test]
+    fn team_work() {
+        let res = roll("2[1] +team 2", &[5, 3, 5, 5, 6], Edition::Sr5);
+        assert_eq!(res.hits, Some(2));
+        assert!(res
+            .details
+            .starts_with("Assistant 1 : **5**, 3 | 1 réussite.\nBonus d’équipe : +1"));
+    }
+
+    #[test]
+    fn seeded_rolls_are_stable() {
+        let setup = RollSetup {
+            seed: 0,
+            system: None,
+            edition: Edition::Sr5,
+            wounds: 0,
+            buy: false,
+        };
+        let dice = |res: Vec<Resolution>| res.iter().map(dice).collect::<Vec<_>>();
+        // recorded rolls must replay identically whatever the version of rand
+        assert_eq!(
+            dice(replay(&setup, &["6", "3 vs 2"]).unwrap()),
+            vec![vec![vec![4, 5, 5, 3, 4, 5]], vec![vec![4, 1, 2]]]
+        );
+    }
+
+    #[test]
+    fn bare_pools_replay() {
+        let (setup, res) = setup_pool(Edition::Sr6, 9);
+        let replayed = replay(&setup, &["9"]).unwrap();
+        assert_eq!(replayed[0].rolls[0].all, res.all);
+    }
+
+    fn error(input: &str) -> String {
+        match shadowrun_tests(input) {
+            Ok(_) => panic!("`{}` should not parse", input),
+            Err(err) => describe_error(input, err),
+        }
+    }
+
+    #[test]
+    fn error_descriptions() {
+        assert_eq!(
+            error("12[4"),
+            "attendu à la colonne 5 : `]`, après la limite.\n```\n12[4\n    ^\n```"
+        );
+        let unclosed = error("12(3");
+        assert!(unclosed.starts_with("attendu à la colonne 5 : `)` ou `,`, après le seuil."));
+        assert!(error("4[2](3x").starts_with("attendu à la colonne 7 : `)` ou `,`"));
+        assert!(error("12[4](3,").starts_with("attendu à la colonne 9 : un intervalle"));
+    }
+}
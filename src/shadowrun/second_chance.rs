@@ -0,0 +1,137 @@
+use crate::{
+    discord::reaction_is_own,
+    error::AVoid,
+    shadowrun::{
+        character::update_active,
+        edition::Edition,
+        history,
+        roll::{simple_summary, ShadowrunRoll},
+    },
+    state::{encode, extract, Embedded},
+};
+use rand::{
+    distributions::{Distribution, Uniform},
+    SeedableRng,
+};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::channel::{Reaction, ReactionType::Unicode},
+    model::id::UserId,
+    utils::MessageBuilder,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SecondChance {
+    pub limit: Option<u64>,
+    pub threshold: Option<u64>,
+    pub dice: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ShadowrunSecondChance {
+    pub roller_raw_id: u64,
+    pub seed: u32,
+    pub original: SecondChance,
+    pub rerolled: Option<Vec<u8>>,
+}
+
+impl SecondChance {
+    // the rerolled dice and the resulting roll; they are drawn from a stream of the roll’s seed
+    // apart from its first dice, so that `sr verify` replays them too
+    pub fn reroll(&self, seed: u32) -> (Vec<u8>, ShadowrunRoll) {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed.into());
+        rng.set_stream(1);
+        let mut kept: Vec<u8> = self
+            .dice
+            .iter()
+            .cloned()
+            .filter(|&single| single >= 5)
+            .collect();
+        let rerolled: Vec<u8> = (0..self.dice.len() - kept.len())
+            .map(|_| Uniform::new_inclusive(1, 6).sample(&mut rng))
+            .collect();
+        kept.extend(&rerolled);
+        (
+            rerolled,
+            ShadowrunRoll::evaluate(kept, self.limit, Edition::Sr5),
+        )
+    }
+}
+
+pub fn react(ctx: &Context, reaction: &Reaction) -> AVoid {
+    if reaction_is_own(ctx, reaction)? {
+        return Ok(());
+    }
+    if !matches!(&reaction.emoji, Unicode(emote) if emote == "🔁") {
+        return Ok(());
+    }
+    let mut msg = reaction.message(ctx)?;
+    let mut data = match extract(ctx, &msg) {
+        Some(Embedded::EShadowrunSecondChance(data)) => data,
+        _ => return Ok(()),
+    };
+    if data.roller_raw_id != reaction.user_id.0 || data.rerolled.is_some() {
+        return Ok(());
+    }
+    let edge_left = match update_active(ctx, reaction.user_id, |sheet| sheet.spend_edge(1))? {
+        Some(Ok(left)) => Some(left),
+        Some(Err(_)) => {
+            reaction.channel_id.send_message(ctx, |m| {
+                m.content(
+                    MessageBuilder::new()
+                        .mention(&UserId(data.roller_raw_id))
+                        .push(", pas assez de chance pour une seconde chance."),
+                )
+            })?;
+            return Ok(());
+        }
+        None => None,
+    };
+    let (rerolled, res) = data.original.reroll(data.seed);
+    let summary = simple_summary(&res, data.original.threshold);
+    history::record_second_chance(ctx, data.seed, data.roller_raw_id, &res, &summary)?;
+    let mut description = msg
+        .embeds
+        .first()
+        .and_then(|embed| embed.description.clone())
+        .unwrap_or_default();
+    description.push_str("\nSeconde chance : ");
+    description
+        .push_str(&ShadowrunRoll::evaluate(rerolled.clone(), None, Edition::Sr5).fmt_details());
+    if let Some(left) = edge_left {
+        description.push_str(&format!("\nChance restante : {}.", left));
+    }
+    data.rerolled = Some(rerolled);
+    let footer = encode(Embedded::EShadowrunSecondChance(data))?;
+    msg.edit(ctx, |m| {
+        m.embed(|e| {
+            e.title(summary)
+                .description(description)
+                .footer(|f| f.text(footer))
+        })
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rerolls_the_misses_from_the_seed() {
+        let original = SecondChance {
+            limit: Some(3),
+            threshold: None,
+            dice: vec![6, 1, 5, 2, 3],
+        };
+        let (rerolled, res) = original.reroll(42);
+        assert_eq!(rerolled.len(), 3);
+        assert_eq!(res.all[..2], [6, 5]);
+        assert_eq!(res.all[2..], rerolled[..]);
+        assert!(res.hits <= 3);
+        let (again, _) = original.reroll(42);
+        assert_eq!(rerolled, again);
+    }
+}
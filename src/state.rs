@@ -2,7 +2,9 @@ use crate::{
     edf::EdfSing,
     error::ARes,
     shadowrun::confirm::ShadowrunConfirm,
+    shadowrun::init::ShadowrunInit,
     shadowrun::plan::ShadowrunPlan,
+    shadowrun::second_chance::ShadowrunSecondChance,
     utils::{find_message_with, find_message_with_limit},
 };
 use base64::{write::EncoderWriter, STANDARD};
@@ -21,15 +23,26 @@ pub enum Embedded {
     EShadowrunPlan(ShadowrunPlan),
     EShadowrunConfirm(ShadowrunConfirm),
     EEdfSing(EdfSing),
+    EShadowrunInit(ShadowrunInit),
+    EShadowrunSecondChance(ShadowrunSecondChance),
+}
+
+// plans were first stored as a unit struct, bincoded as the bare variant index
+#[derive(Deserialize)]
+enum LegacyEmbedded {
+    EShadowrunPlan,
 }
 
 pub fn encode(input: Embedded) -> ARes<String> {
-    let bin = serialize(&input)?;
+    pack(&serialize(&input)?)
+}
+
+fn pack(bin: &[u8]) -> ARes<String> {
     let mut buf = vec![];
     {
         let mut base = EncoderWriter::new(&mut buf, STANDARD);
         let mut snap = GzEncoder::new(&mut base, Compression::best());
-        snap.write_all(&bin)?;
+        snap.write_all(bin)?;
     }
     let mut based = String::from_utf8(buf)?;
     let mut split = String::new();
@@ -43,22 +56,38 @@ pub fn encode(input: Embedded) -> ARes<String> {
 }
 
 pub fn decode(input: &str) -> Option<Embedded> {
+    deserialize::<Embedded>(&unpack(input)?).ok()
+}
+
+fn unpack(input: &str) -> Option<Vec<u8>> {
     let no_split = input.replace("\n", "");
     if let Ok(un_base) = base64::decode(&no_split) {
         let mut un_snap = GzDecoder::new(un_base.as_slice());
         let mut buf = vec![];
         if un_snap.read_to_end(&mut buf).is_ok() {
-            let a = deserialize::<Embedded>(&buf);
-            return a.ok();
+            return Some(buf);
         }
     }
     None
 }
 
+fn is_legacy_plan(input: &str) -> bool {
+    match unpack(input) {
+        Some(buf) => matches!(
+            deserialize::<LegacyEmbedded>(&buf),
+            Ok(LegacyEmbedded::EShadowrunPlan) if buf.len() == 4
+        ),
+        None => false,
+    }
+}
+
 pub fn extract(ctx: &Context, message: &Message) -> Option<Embedded> {
     if message.is_own(ctx) {
         if let Some(embed) = message.embeds.first() {
             if let Some(footer) = &embed.footer {
+                if is_legacy_plan(&footer.text) {
+                    return Some(Embedded::EShadowrunPlan(ShadowrunPlan::legacy(message)));
+                }
                 return decode(&footer.text);
             }
         }
@@ -89,3 +118,36 @@ pub fn find_by_state_limit(
         limit,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shadowrun::init::ShadowrunInit;
+
+    #[test]
+    fn legacy_plans() {
+        let legacy = pack(&[0, 0, 0, 0]).unwrap();
+        assert!(is_legacy_plan(&legacy));
+        assert!(decode(&legacy).is_none());
+        let plan = encode(Embedded::EShadowrunPlan(ShadowrunPlan {
+            origin_raw_id: 1,
+            start_timestamp: 0,
+            length: 7,
+            week: 0,
+            slot: None,
+            slots: 1,
+            quorum: 3,
+            closed: false,
+        }))
+        .unwrap();
+        assert!(!is_legacy_plan(&plan));
+        assert!(matches!(decode(&plan), Some(Embedded::EShadowrunPlan(_))));
+        let init = encode(Embedded::EShadowrunInit(ShadowrunInit {
+            turn: 0,
+            pass: 0,
+            combatants: vec![],
+        }))
+        .unwrap();
+        assert!(!is_legacy_plan(&init));
+    }
+}
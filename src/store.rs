@@ -0,0 +1,50 @@
+use crate::error::ARes;
+use anyhow::Context as _;
+use serde::{de::DeserializeOwned, Serialize};
+use serenity::{client::Context, prelude::Mutex as SerenityMutex};
+use std::{env, fs, io::ErrorKind, path::PathBuf, sync::Arc};
+
+const DEFAULT_DATA_DIR: &str = "data";
+
+pub struct StoreLockKey;
+impl typemap::Key for StoreLockKey {
+    type Value = Arc<SerenityMutex<()>>;
+}
+
+fn path(name: &str) -> PathBuf {
+    let dir = env::var("SPARKY_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.to_owned());
+    PathBuf::from(dir).join(format!("{}.json", name))
+}
+
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> ARes<T> {
+    match fs::read(path(name)) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)
+            .with_context(|| format!("corrupted store `{}`", name))?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn update<T, R>(ctx: &Context, name: &str, f: impl FnOnce(&mut T) -> R) -> ARes<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let lock = ctx
+        .data
+        .read()
+        .get::<StoreLockKey>()
+        .context("store lock not in data")?
+        .clone();
+    let _guard = lock.lock();
+    let mut value = load(name)?;
+    let res = f(&mut value);
+    let path = path(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // written aside then renamed so that a crash never leaves a truncated store
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(&value)?)?;
+    fs::rename(tmp, path)?;
+    Ok(res)
+}